@@ -1,10 +1,57 @@
-use std::io::Read;
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{Read, Write};
 use std::io;
+use std::num::NonZeroUsize;
+use std::ops::Range;
+use std::ptr;
+use std::slice;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
 use itertools::*;
 
+mod analysis;
+mod cell;
+mod codegen;
+mod execution;
+mod input;
+mod interpreter;
+#[cfg(feature = "jit")]
+mod jit;
+mod optimizer;
+mod session;
+mod tape;
+#[cfg(test)]
+mod testing;
+
+pub use analysis::{Lint, LintKind};
+pub use cell::Cell;
+pub use execution::{Execution, ExecutionReport};
+pub use input::NormalizeLineEndings;
+pub use interpreter::{Interpreter, Snapshot, StepResult, Yield, TIMEOUT_CHECK_INTERVAL};
+pub use session::Session;
+pub use tape::{dump_tape, dump_tape_with_signedness, TapeState};
+
+pub const DEFAULT_MEMORY_CAPACITY: usize = 1048576;
+
+/// Splits a source of the form `program!input`, which some brainfuck environments use to ship the input with the program, at the first `!`.
+/// Without a `!`, the whole source is the program and the input is empty. Comments are not skipped, so a `!` in one splits the source too.
+pub fn split_embedded_input(source: &str) -> (&str, &str) {
+    match source.find('!') {
+        Some(index) => (&source[..index], &source[index + 1..]),
+        None => (source, ""),
+    }
+}
+
+/// Zeroes a tape so it can be passed to `Program::run_reusing` again without allocating a new one.
+pub fn clear_memory<C: Cell>(memory: &mut [C]) {
+    memory.fill(C::default());
+}
+
 /*
 // Usage:
-if let Some(program) = brainfuck::Program::parse(program) {
+if let Ok(program) = brainfuck::Program::parse(program) {
 
     let mut memory = vec![0u8; 1048576];
     program.run(&mut memory);
@@ -12,9 +59,9 @@ if let Some(program) = brainfuck::Program::parse(program) {
 */
 
 
-/// Increments/Decrements are parameterized with usize to be able to use the same data structure for optimization (see Self::optimize method).
-#[derive(PartialEq, Clone, Copy, Debug)]
-enum OpCode {
+/// A single command as it appears in the source, see `Program::op_codes`. Runs of them are combined into `Instruction`s when parsing.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub enum OpCode {
     /// ++ptr
     IncrementPointer,
     /// --ptr
@@ -31,10 +78,16 @@ enum OpCode {
     LoopHead,
     /// }
     LoopEnd,
+    /// Non-standard `#`, only recognized with `ParseOptions::breakpoints`
+    Breakpoint,
+    /// Non-standard `?`, only recognized with `ParseOptions::dump_tape`
+    DumpTape,
+    /// Non-standard `@label`, only recognized with `ParseOptions::marks`
+    Mark(String),
 }
 
 impl OpCode {
-    fn parse(c: char) -> Option<OpCode> {
+    fn parse(c: char, options: &ParseOptions) -> Option<OpCode> {
         match c {
             '>' => Some(OpCode::IncrementPointer),
             '<' => Some(OpCode::DecrementPointer),
@@ -44,11 +97,13 @@ impl OpCode {
             ',' => Some(OpCode::GetChar),
             '[' => Some(OpCode::LoopHead),
             ']' => Some(OpCode::LoopEnd),
+            '#' if options.breakpoints => Some(OpCode::Breakpoint),
+            '?' if options.dump_tape => Some(OpCode::DumpTape),
             _ => None,
         }
     }
 
-    fn create_optimized_instruction(&self, n: usize) -> Instruction {
+    fn create_optimized_instruction(&self, n: usize) -> Instruction<Unbound> {
         assert!(n > 1);
         assert!(self.is_run_length_optimizable());
 
@@ -57,20 +112,36 @@ impl OpCode {
             OpCode::IncrementPointer => Instruction::IncrementPointer(n),
             OpCode::DecrementValue => Instruction::DecrementValue(n),
             OpCode::IncrementValue => Instruction::IncrementValue(n),
-            _ => panic!("Cannot optimize non-incrementing OpCode with more than 1 repetition"),
+            OpCode::PutChar => Instruction::PutChar(n),
+            OpCode::GetChar => Instruction::GetChar(n),
+            _ => panic!("Cannot optimize OpCode with more than 1 repetition"),
         }
     }
 
-    fn as_instruction(&self) -> Instruction {
+    fn as_instruction(&self) -> Instruction<Unbound> {
         match self {
             OpCode::DecrementPointer => Instruction::DecrementPointer(1),
             OpCode::IncrementPointer => Instruction::IncrementPointer(1),
             OpCode::DecrementValue => Instruction::DecrementValue(1),
             OpCode::IncrementValue => Instruction::IncrementValue(1),
-            OpCode::PutChar => Instruction::PutChar,
-            OpCode::GetChar => Instruction::GetChar,
-            OpCode::LoopHead => Instruction::LoopHead(usize::MAX),
-            OpCode::LoopEnd => Instruction::LoopEnd(usize::MAX),
+            OpCode::PutChar => Instruction::PutChar(1),
+            OpCode::GetChar => Instruction::GetChar(1),
+            OpCode::LoopHead => Instruction::LoopHead(Unbound),
+            OpCode::LoopEnd => Instruction::LoopEnd(Unbound),
+            OpCode::Breakpoint => Instruction::Breakpoint,
+            OpCode::DumpTape => Instruction::DumpTape,
+            OpCode::Mark(ref label) => Instruction::Mark(label.clone()),
+        }
+    }
+
+    /// The op code undoing this one, if there is any
+    fn opposite(&self) -> Option<OpCode> {
+        match self {
+            OpCode::DecrementPointer => Some(OpCode::IncrementPointer),
+            OpCode::IncrementPointer => Some(OpCode::DecrementPointer),
+            OpCode::DecrementValue => Some(OpCode::IncrementValue),
+            OpCode::IncrementValue => Some(OpCode::DecrementValue),
+            _ => None,
         }
     }
 
@@ -81,195 +152,1878 @@ impl OpCode {
                 | OpCode::IncrementPointer
                 | OpCode::DecrementValue
                 | OpCode::IncrementValue
+                | OpCode::PutChar
+                | OpCode::GetChar
         )
     }
 }
 
-#[derive(PartialEq, Clone, Copy, Debug)]
-enum Instruction {
+/// The command as it is written in the source
+impl fmt::Display for OpCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OpCode::IncrementPointer => write!(f, ">"),
+            OpCode::DecrementPointer => write!(f, "<"),
+            OpCode::IncrementValue => write!(f, "+"),
+            OpCode::DecrementValue => write!(f, "-"),
+            OpCode::PutChar => write!(f, "."),
+            OpCode::GetChar => write!(f, ","),
+            OpCode::LoopHead => write!(f, "["),
+            OpCode::LoopEnd => write!(f, "]"),
+            OpCode::Breakpoint => write!(f, "#"),
+            OpCode::DumpTape => write!(f, "?"),
+            OpCode::Mark(label) => write!(f, "@{}", label),
+        }
+    }
+}
+
+/// The lowered form of a program, produced by `Program::parse` and executed by `Program::run`.
+/// Loop addresses are indices into `Program::instructions`. Before the loops are bound, `Address` is `Unbound`.
+#[derive(PartialEq, Clone, Debug)]
+pub enum Instruction<Address = usize> {
     IncrementPointer(usize),
     DecrementPointer(usize),
     IncrementValue(usize),
     DecrementValue(usize),
-    PutChar,
-    GetChar,
-    LoopHead(usize /* pointer to end instruction */),
-    LoopEnd(usize /* pointer to head instruction */),
+    /// Writes the current cell `n` times, lowered from runs of `.`
+    PutChar(usize),
+    /// Reads `n` bytes into the current cell, keeping the last one. Lowered from runs of `,`
+    GetChar(usize),
+    LoopHead(Address /* pointer to end instruction */),
+    LoopEnd(Address /* pointer to head instruction */),
+    /// *ptr = 0, lowered from [-] and [+]
+    SetZero,
+    /// *ptr = n (modulo the cell size), lowered from [-] followed by increments/decrements
+    SetValue(isize),
+    /// Zeroes `count` cells starting at the current one and leaves the pointer on the last of them, lowered from [-]>[-]>[-]
+    FillZero { count: usize },
+    /// for each (offset, factor): ptr[offset] += *ptr * factor; then *ptr = 0
+    /// Lowered from loops like [->+>++<<]
+    MultiplyAdd(Vec<(isize /* offset */, isize /* factor */)>),
+    /// for each offset: ptr[offset] += *ptr; then *ptr = 0
+    /// A `MultiplyAdd` with every factor 1, lowered from loops like [->>>+<<<] or [->+>+<<]. The value is read once before the
+    /// first addition, so an offset of 0 is overwritten by the final *ptr = 0 and an offset listed twice receives the value twice.
+    /// The optimizer only emits distinct offsets other than 0, everything else stays a `MultiplyAdd`.
+    CopyTo { targets: Vec<isize> },
+    /// while (*ptr) ptr += n, lowered from [>]
+    ScanRight(usize),
+    /// while (*ptr) ptr -= n, lowered from [<]
+    ScanLeft(usize),
+    /// ptr[offset] += amount (modulo the cell size), lowered from pointer moves around value changes like >+++<
+    AddAtOffset { offset: isize, amount: isize },
+    /// Non-standard `#`, reports the current state to a callback
+    Breakpoint,
+    /// Non-standard `?`, prints the cells around the data pointer to stderr
+    DumpTape,
+    /// Non-standard `@label`, reports reaching the label to a callback
+    Mark(String),
+}
+
+/// The address of a loop bracket whose partner is not known yet, used by the optimizer before `Program::bind`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub(crate) struct Unbound;
+
+impl<Address> Instruction<Address> {
+    /// Replaces the address of a loop bracket with `f(address)`, the other instructions are kept as they are
+    fn map_address<B>(self, f: impl FnOnce(Address) -> B) -> Instruction<B> {
+        match self {
+            Instruction::IncrementPointer(n) => Instruction::IncrementPointer(n),
+            Instruction::DecrementPointer(n) => Instruction::DecrementPointer(n),
+            Instruction::IncrementValue(n) => Instruction::IncrementValue(n),
+            Instruction::DecrementValue(n) => Instruction::DecrementValue(n),
+            Instruction::PutChar(n) => Instruction::PutChar(n),
+            Instruction::GetChar(n) => Instruction::GetChar(n),
+            Instruction::LoopHead(address) => Instruction::LoopHead(f(address)),
+            Instruction::LoopEnd(address) => Instruction::LoopEnd(f(address)),
+            Instruction::SetZero => Instruction::SetZero,
+            Instruction::SetValue(value) => Instruction::SetValue(value),
+            Instruction::FillZero { count } => Instruction::FillZero { count },
+            Instruction::MultiplyAdd(targets) => Instruction::MultiplyAdd(targets),
+            Instruction::CopyTo { targets } => Instruction::CopyTo { targets },
+            Instruction::ScanRight(n) => Instruction::ScanRight(n),
+            Instruction::ScanLeft(n) => Instruction::ScanLeft(n),
+            Instruction::AddAtOffset { offset, amount } => Instruction::AddAtOffset { offset, amount },
+            Instruction::Breakpoint => Instruction::Breakpoint,
+            Instruction::DumpTape => Instruction::DumpTape,
+            Instruction::Mark(label) => Instruction::Mark(label),
+        }
+    }
+
+    /// The variant name without its operands
+    pub fn name(&self) -> &'static str {
+        match self {
+            Instruction::IncrementPointer(_) => "IncrementPointer",
+            Instruction::DecrementPointer(_) => "DecrementPointer",
+            Instruction::IncrementValue(_) => "IncrementValue",
+            Instruction::DecrementValue(_) => "DecrementValue",
+            Instruction::PutChar(_) => "PutChar",
+            Instruction::GetChar(_) => "GetChar",
+            Instruction::LoopHead(_) => "LoopHead",
+            Instruction::LoopEnd(_) => "LoopEnd",
+            Instruction::SetZero => "SetZero",
+            Instruction::SetValue(_) => "SetValue",
+            Instruction::FillZero { .. } => "FillZero",
+            Instruction::MultiplyAdd(_) => "MultiplyAdd",
+            Instruction::CopyTo { .. } => "CopyTo",
+            Instruction::ScanRight(_) => "ScanRight",
+            Instruction::ScanLeft(_) => "ScanLeft",
+            Instruction::AddAtOffset { .. } => "AddAtOffset",
+            Instruction::Breakpoint => "Breakpoint",
+            Instruction::DumpTape => "DumpTape",
+            Instruction::Mark(_) => "Mark",
+        }
+    }
+}
+
+/// A compact notation based on the source commands: runs are followed by their length (`>4`, but `>` for a single one), loop brackets
+/// by the address of their partner (`[→12`). The optimized instructions are written as `=5` for `SetValue(5)`, `=0×3` for `FillZero { count: 3 }`, `[>2]` for `ScanRight(2)`,
+/// `+3@-1` for adding 3 to the cell at offset -1, `*(+1@1 +2@2)` for `MultiplyAdd` and `*(@1 @3)` for `CopyTo`.
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let run = |f: &mut fmt::Formatter, command: char, n: usize| {
+            if n == 1 {
+                write!(f, "{}", command)
+            } else {
+                write!(f, "{}{}", command, n)
+            }
+        };
+
+        match *self {
+            Instruction::IncrementPointer(n) => run(f, '>', n),
+            Instruction::DecrementPointer(n) => run(f, '<', n),
+            Instruction::IncrementValue(n) => run(f, '+', n),
+            Instruction::DecrementValue(n) => run(f, '-', n),
+            Instruction::PutChar(n) => run(f, '.', n),
+            Instruction::GetChar(n) => run(f, ',', n),
+            Instruction::LoopHead(end) => write!(f, "[→{}", end),
+            Instruction::LoopEnd(head) => write!(f, "]←{}", head),
+            Instruction::SetZero => write!(f, "=0"),
+            Instruction::SetValue(value) => write!(f, "={}", value),
+            Instruction::FillZero { count } => write!(f, "=0×{}", count),
+            Instruction::MultiplyAdd(ref targets) => {
+                let targets = targets.iter().map(|(offset, factor)| format!("{:+}@{}", factor, offset)).join(" ");
+                write!(f, "*({})", targets)
+            }
+            Instruction::CopyTo { ref targets } => {
+                let targets = targets.iter().map(|offset| format!("@{}", offset)).join(" ");
+                write!(f, "*({})", targets)
+            }
+            Instruction::ScanRight(n) => {
+                write!(f, "[")?;
+                run(f, '>', n)?;
+                write!(f, "]")
+            }
+            Instruction::ScanLeft(n) => {
+                write!(f, "[")?;
+                run(f, '<', n)?;
+                write!(f, "]")
+            }
+            Instruction::AddAtOffset { offset, amount } => write!(f, "{:+}@{}", amount, offset),
+            Instruction::Breakpoint => write!(f, "#"),
+            Instruction::DumpTape => write!(f, "?"),
+            Instruction::Mark(ref label) => write!(f, "@{}", label),
+        }
+    }
+}
+
+/// Location of a character in the original source. `line` and `column` are 1-based, the column is counted in chars.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct SourceSpan {
+    pub line: usize,
+    pub column: usize,
+    pub byte_offset: usize,
+}
+
+impl SourceSpan {
+    /// Finds the line and column of `byte_offset` in `code`, e.g. to locate the ranges of `Program::source_map`.
+    pub fn locate(code: &str, byte_offset: usize) -> SourceSpan {
+        let before = &code[..byte_offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+        SourceSpan {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+            byte_offset,
+        }
+    }
+}
+
+impl fmt::Display for SourceSpan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}, column {}", self.line, self.column)
+    }
+}
+
+/// What `,` does to the current cell once the input is exhausted.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum EofBehavior {
+    /// Leave the cell as it is
+    #[default]
+    Unchanged,
+    /// Set the cell to 0
+    Zero,
+    /// Set the cell to -1 (255)
+    MinusOne,
+}
+
+/// How `.` writes the current cell.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum OutputEncoding {
+    /// The cell as a single byte, cells wider than 8 bits are truncated
+    #[default]
+    Byte,
+    /// The value of the cell as a decimal number followed by a space, e.g. `65 ` instead of `A`
+    Decimal,
+}
+
+/// Whether numbers shown for cells, by `OutputEncoding::Decimal` and `dump_tape_with_signedness`, are read as signed, like some
+/// brainfuck variants do. This only changes how values are presented, the arithmetic is the same either way.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum Signedness {
+    /// `0..=255` for 8-bit cells
+    #[default]
+    Unsigned,
+    /// Two's complement of the cell's width, `-128..=127` for 8-bit cells. A cell holding 200 is shown as -56.
+    Signed,
+}
+
+impl Signedness {
+    /// The value of `cell` as a decimal number
+    pub fn render<C: Cell>(self, cell: C) -> String {
+        match self {
+            Signedness::Unsigned => cell.to_usize().to_string(),
+            Signedness::Signed => cell.to_isize().to_string(),
+        }
+    }
+}
+
+/// Transforms the bytes `.` writes with `OutputEncoding::Byte`, decimal output is plain ASCII already.
+/// Masking changes what programs print and is only meant for passing their output through channels that require 7-bit ASCII.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum OutputMask {
+    /// Write the bytes unchanged
+    #[default]
+    Off,
+    /// Clear the highest bit of every byte (`byte & 0x7F`), so bytes above 127 map to ASCII
+    SevenBit,
+}
+
+impl OutputMask {
+    pub fn apply(self, byte: u8) -> u8 {
+        match self {
+            OutputMask::Off => byte,
+            OutputMask::SevenBit => byte & 0x7F,
+        }
+    }
+}
+
+/// What happens when the data pointer leaves the tape.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum TapeMode {
+    /// Moving before the first or past the last cell is an error (a growable tape grows instead)
+    #[default]
+    Strict,
+    /// The tape is circular, positions are taken modulo its length. A growable tape never grows in this mode.
+    Wrap,
+    /// A growable tape also grows to the left when moving before the first cell, see `Interpreter::origin`.
+    /// A borrowed tape behaves like in `Strict`.
+    Bidirectional,
+}
+
+/// What `+` and `-` do at the bounds of a cell.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub enum CellArithmetic {
+    /// 255 + 1 is 0 and 0 - 1 is 255 (for 8-bit cells), as in standard brainfuck
+    #[default]
+    Wrapping,
+    /// 255 + 1 stays 255 and 0 - 1 stays 0.
+    /// Optimizations relying on wrapping, like lowering multiplication loops, are skipped.
+    /// The code generators and the JIT only support wrapping arithmetic.
+    Saturating,
+}
+
+/// Dialect switches for `Program::parse_with_options`. The defaults parse standard brainfuck.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct ParseOptions {
+    /// Recognize `#` as a breakpoint instead of treating it as a comment
+    pub breakpoints: bool,
+    /// Recognize `?` as `Instruction::DumpTape` instead of treating it as a comment
+    pub dump_tape: bool,
+    /// Width of the cells the program will run on, e.g. `Some(u8::BITS)`.
+    /// Runs of `+` and `-` are then folded modulo the cell size, so 256 `+` are dropped for 8-bit cells.
+    /// Running the program with narrower cells is fine, wider cells would observe the difference.
+    pub cell_bits: Option<u32>,
+    /// Keep `@label` as `Instruction::Mark` instead of treating it as a comment.
+    /// A label consists of letters, digits and `_`, a lone `@` is still a comment.
+    pub marks: bool,
+    /// The program continues on a tape left by another program, e.g. with `Program::run_io_at`.
+    /// The current cell is not assumed to be zero at the start then, which otherwise lets leading loops be dropped.
+    pub fragment: bool,
+    /// Reject programs with loops nested deeper than this, e.g. `Some(1)` allows `[-]>[-]` but not `[[-]]`. Unlimited if `None`.
+    /// This lets sandboxes turn away hostile input early.
+    pub max_loop_depth: Option<usize>,
+    /// The arithmetic the program is optimized for and runs with
+    pub arithmetic: CellArithmetic,
+    /// Turn every command into its own instruction, without cancelling, combining or optimizing anything.
+    /// This is much slower, but gives a reference to check the optimizer against, see `Program::run_both_and_compare`.
+    pub unoptimized: bool,
+}
+
+impl ParseOptions {
+    /// The number of values a cell holds if they wrap around at a known width.
+    /// Adding the cell size is a no-op then, so value changes only matter modulo it.
+    fn cell_size(&self) -> Option<usize> {
+        match self.arithmetic {
+            CellArithmetic::Wrapping => self.cell_bits.and_then(|bits| 1usize.checked_shl(bits)),
+            CellArithmetic::Saturating => None,
+        }
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct ExecutionOptions {
+    pub eof_behavior: EofBehavior,
+    pub tape_mode: TapeMode,
+    pub output_encoding: OutputEncoding,
+    pub output_mask: OutputMask,
+    pub signedness: Signedness,
+    /// Treat the input as exactly this many bytes, e.g. a fixed-length seed when fuzzing: if it is shorter, the missing bytes are
+    /// read as 0, and once this many bytes were read, `,` sees the end of the input and the rest of it is not consumed.
+    /// `None` reads the input as it is.
+    pub input_length: Option<u64>,
+    /// Take positions modulo this many cells, like old interpreters with a 32-bit data pointer did for `1 << 32`: moving left from
+    /// cell 0 continues at the end of the address space, which is past the end of any smaller tape. `None` uses the native
+    /// range of the pointer. The tape should not be longer than the address space. Ignored in `TapeMode::Wrap`, which already
+    /// takes positions modulo the tape length.
+    pub address_space: Option<NonZeroUsize>,
+    /// Stop after executing this many instructions. Unlimited if `None`.
+    pub max_steps: Option<u64>,
+    /// Stop with `Halt::OutputLimitReached` instead of writing more than this many bytes. Unlimited if `None`.
+    /// A `.` whose decimal number does not fit anymore writes as much of it as fits.
+    pub max_output: Option<u64>,
+    /// Stop once this much time passed since the interpreter was created, including time spent waiting for input.
+    /// Unlimited if `None`. The clock is only read every `TIMEOUT_CHECK_INTERVAL` steps, so the run can take slightly longer.
+    pub timeout: Option<Duration>,
+    /// Stop before the next instruction once this flag is set, e.g. by a SIGINT handler.
+    pub interrupt: Option<Interrupt>,
+}
+
+/// A flag that stops a running interpreter with `Halt::Interrupted` when it is set from elsewhere, like a signal handler or another thread.
+/// Two of them are equal if they refer to the same flag.
+#[derive(Clone, Copy, Debug)]
+pub struct Interrupt(&'static AtomicBool);
+
+impl Interrupt {
+    pub fn new(flag: &'static AtomicBool) -> Interrupt {
+        Interrupt(flag)
+    }
+
+    pub fn is_set(self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+impl PartialEq for Interrupt {
+    fn eq(&self, other: &Interrupt) -> bool {
+        ptr::eq(self.0, other.0)
+    }
+}
+
+impl Eq for Interrupt {}
+
+/// Why the execution of a program stopped.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Halt {
+    /// The program ran to its end
+    Finished,
+    /// `ExecutionOptions::max_steps` instructions were executed
+    StepLimitReached,
+    /// A `.` would have written more than `ExecutionOptions::max_output` bytes
+    OutputLimitReached,
+    /// `ExecutionOptions::timeout` has passed
+    Timeout,
+    /// The flag of `ExecutionOptions::interrupt` was set
+    Interrupted,
+}
+
+#[derive(Debug)]
+pub enum RuntimeError {
+    /// The data pointer moved past the last cell. `position` is the index it tried to access.
+    PointerOverflow { position: usize },
+    /// The data pointer moved before the first cell
+    PointerUnderflow,
+    /// Reading the input or writing the output failed
+    Io(io::Error),
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeError::PointerOverflow { position } => write!(f, "data pointer overflow at cell {}", position),
+            RuntimeError::PointerUnderflow => write!(f, "data pointer underflow"),
+            RuntimeError::Io(error) => write!(f, "I/O error: {}", error),
+        }
+    }
+}
+
+impl Error for RuntimeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RuntimeError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RuntimeError {
+    fn from(error: io::Error) -> Self {
+        RuntimeError::Io(error)
+    }
+}
+
+/// Counters collected by `Program::run_io_with_stats` and `Program::execute`.
+#[derive(PartialEq, Eq, Clone, Debug, Default)]
+pub struct ExecutionStats {
+    /// Total number of executed instructions
+    pub steps: u64,
+    /// Executed instructions per kind, keyed by `Instruction::name`
+    pub instruction_counts: BTreeMap<&'static str, u64>,
+    /// The rightmost cell the data pointer reached
+    pub peak_data_pointer: usize,
+    /// Number of times a loop body was entered, either from its `[` or by jumping back from its `]`.
+    /// Loops lowered to a single instruction (like `SetZero`) are not counted.
+    pub loop_iterations: u64,
+    /// The same counters per loop that was entered at least once, keyed by the address of its `LoopHead`.
+    /// Map them back to the source with `Program::source_map`.
+    pub loops: BTreeMap<usize, LoopStats>,
+}
+
+/// Counters of a single loop, see `ExecutionStats::loops`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Default)]
+pub struct LoopStats {
+    /// Number of times the loop was entered from its `[`, i.e. with a non-zero cell
+    pub entries: u64,
+    /// Number of times the loop body was entered, including the entries
+    pub iterations: u64,
+}
+
+/// A suspicious pattern found while running a program with `Program::run_io_with_lints`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum RuntimeLint {
+    /// The instruction at `address` tested or printed the cell at `position`, which the program never wrote.
+    /// The program relies on the tape starting with zeros there, or assumed it to be populated.
+    UninitializedRead { address: usize, position: usize },
+    /// The instruction at `address` moved the data pointer left of the cell the program started on, which only `TapeMode::Wrap` allows
+    PointerBelowStart { address: usize },
+}
+
+impl fmt::Display for RuntimeLint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RuntimeLint::UninitializedRead { position, .. } => write!(f, "read cell {}, which was never written", position),
+            RuntimeLint::PointerBelowStart { .. } => write!(f, "moved the data pointer left of the first cell"),
+        }
+    }
+}
+
+/// How a run by `Program::run_with_result` ended.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct RunResult {
+    pub halt: Halt,
+    /// The index of the cell the data pointer was on when the program stopped
+    pub final_pointer: usize,
+}
+
+/// The timing of a run by `Program::bench_run`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub struct BenchReport {
+    pub halt: Halt,
+    pub elapsed: Duration,
+    /// Number of executed instructions. Runs like `+++` and lowered loops count as a single instruction.
+    pub steps: u64,
+}
+
+impl BenchReport {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.steps as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum ParseError {
+    /// A `]` without a matching `[`.
+    /// `span` points at the first such bracket in the source.
+    UnexpectedLoopEnd { span: SourceSpan },
+    /// A `[` without a matching `]`.
+    /// `span` points at the outermost such bracket in the source.
+    UnclosedLoopHead { span: SourceSpan },
+    /// A `[` opens a loop nested deeper than `ParseOptions::max_loop_depth`.
+    /// `span` points at the first such bracket in the source, `depth` is the number of loops around it plus itself.
+    LoopNestingTooDeep { depth: usize, span: SourceSpan },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::UnexpectedLoopEnd { span } => {
+                write!(f, "unbalanced brackets: `]` without a matching `[` at {}", span)
+            }
+            ParseError::UnclosedLoopHead { span } => {
+                write!(f, "unbalanced brackets: `[` without a matching `]` at {}", span)
+            }
+            ParseError::LoopNestingTooDeep { depth, span } => {
+                write!(f, "loops nested too deeply: the `[` at {} opens a loop at depth {}", span, depth)
+            }
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Unbalanced brackets in the instructions given to `Program::from_instructions`
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum BindError {
+    /// A `LoopEnd` without a matching `LoopHead`.
+    /// `index` is the first such instruction.
+    UnexpectedLoopEnd { index: usize },
+    /// A `LoopHead` without a matching `LoopEnd`.
+    /// `index` is the outermost such instruction.
+    UnclosedLoopHead { index: usize },
+}
+
+impl fmt::Display for BindError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BindError::UnexpectedLoopEnd { index } => {
+                write!(f, "unbalanced brackets: loop end without a matching loop head at instruction {}", index)
+            }
+            BindError::UnclosedLoopHead { index } => {
+                write!(f, "unbalanced brackets: loop head without a matching loop end at instruction {}", index)
+            }
+        }
+    }
+}
+
+impl Error for BindError {}
+
+/// Hashes everything written to it with 64 bit FNV-1a
+struct Fnv1aWriter {
+    hash: u64,
+}
+
+impl Fnv1aWriter {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+
+    fn new() -> Fnv1aWriter {
+        Fnv1aWriter { hash: Self::OFFSET_BASIS }
+    }
+}
+
+impl Write for Fnv1aWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            self.hash = (self.hash ^ u64::from(byte)).wrapping_mul(Self::PRIME);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 pub struct Program {
     instructions: Vec<Instruction>,
+    /// Parallel to `instructions`: the range of source bytes each instruction was lowered from
+    source_map: Vec<Range<usize>>,
+    /// Parallel to `instructions`: the number of loops around each instruction
+    loop_depths: Vec<usize>,
+    /// Number of commands in the source, before optimizing
+    op_code_count: usize,
+    arithmetic: CellArithmetic,
+    /// The commands and options the program was parsed with, to lower it again for `run_both_and_compare`.
+    /// `None` if it was built with `from_instructions`.
+    parsed_from: Option<(Vec<OpCode>, ParseOptions)>,
 }
 
 impl Program {
-    pub fn parse(code: &str) -> Option<Program> {
-        let op_codes: Vec<OpCode> = code.chars().filter_map(OpCode::parse).collect();
+    /// Parses and optimizes standard brainfuck. Everything but the eight commands is a comment.
+    /// A source without any commands, like an empty or comment-only file, is a valid program without instructions that finishes immediately.
+    pub fn parse(code: &str) -> Result<Program, ParseError> {
+        Self::parse_with_options(code, &ParseOptions::default())
+    }
+
+    /// Checks the source like `parse` does without lowering it, e.g. to validate while editing.
+    /// Succeeds exactly if `parse` would, for any `ParseOptions` without a `max_loop_depth`.
+    pub fn validate(code: &str) -> Result<(), ParseError> {
+        let mut depth = 0usize;
+        // Every loop head opened at depth 0 before this one has been closed, so this is the outermost unclosed one
+        let mut outermost_loop_head = None;
+
+        let mut line = 1;
+        let mut column = 1;
+        for (byte_offset, c) in code.char_indices() {
+            let span = SourceSpan { line, column, byte_offset };
+            match c {
+                '[' => {
+                    if depth == 0 {
+                        outermost_loop_head = Some(span);
+                    }
+                    depth += 1;
+                }
+                ']' if depth == 0 => return Err(ParseError::UnexpectedLoopEnd { span }),
+                ']' => depth -= 1,
+                _ => {}
+            }
+
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+        }
 
-        if !Self::check(&op_codes) {
-            return None;
+        match outermost_loop_head {
+            Some(span) if depth > 0 => Err(ParseError::UnclosedLoopHead { span }),
+            _ => Ok(()),
         }
+    }
+
+    pub fn parse_with_options(code: &str, options: &ParseOptions) -> Result<Program, ParseError> {
+        Self::parse_chars(code.chars(), options)
+    }
 
-        let instructions = Self::bind(&op_codes);
+    /// Same as `parse_with_options`, but takes the source one char at a time, e.g. decoded while it is read from a file.
+    /// Only the op codes are kept in memory, not the source.
+    pub fn parse_chars<I: IntoIterator<Item = char>>(chars: I, options: &ParseOptions) -> Result<Program, ParseError> {
+        Self::lower(Self::lex(chars.into_iter(), options), options)
+    }
 
-        Some(Program { instructions })
+    /// The commands in the source in order, before anything is cancelled, combined or bound, e.g. to count them or to transform
+    /// them before continuing with `Program::from_op_codes`.
+    pub fn op_codes(code: &str, options: &ParseOptions) -> Vec<OpCode> {
+        Self::lex(code.chars(), options).map(|(op_code, _)| op_code).collect()
     }
 
-    fn check(op_codes: &[OpCode]) -> bool {
-        Self::has_balanced_brackets(&op_codes)
+    /// Optimizes and binds op codes like `parse_with_options` does with the ones it finds in the source.
+    /// There is no source, so errors and `source_map` locate the op codes as if they were written one char each on a single line.
+    pub fn from_op_codes(op_codes: Vec<OpCode>, options: &ParseOptions) -> Result<Program, ParseError> {
+        let spans = (0..op_codes.len()).map(|index| SourceSpan {
+            line: 1,
+            column: index + 1,
+            byte_offset: index,
+        });
+        Self::lower(op_codes.into_iter().zip(spans), options)
     }
 
-    fn has_balanced_brackets(op_codes: &[OpCode]) -> bool {
-        let mut unclosed_loop_heads: isize = 0;
+    /// Everything `parse_chars` does after lexing
+    fn lower(op_codes: impl Iterator<Item = (OpCode, SourceSpan)>, options: &ParseOptions) -> Result<Program, ParseError> {
+        let mut parsed_op_codes = Vec::new();
+        let op_codes = op_codes.inspect(|(op_code, _)| parsed_op_codes.push(op_code.clone()));
+        let (op_codes, spans) = if options.unoptimized {
+            op_codes.unzip()
+        } else {
+            optimizer::cancel_opposites(op_codes, options)
+        };
+
+        // Cancelling never removes brackets, so checking them afterwards finds the same errors
+        Self::check(&op_codes, &spans, options)?;
+
+        let (instructions, source_map, loop_depths) = Self::bind(&op_codes, &spans, options);
+
+        Ok(Program {
+            instructions,
+            source_map,
+            loop_depths,
+            op_code_count: parsed_op_codes.len(),
+            arithmetic: options.arithmetic,
+            parsed_from: Some((parsed_op_codes, *options)),
+        })
+    }
+
+    /// Builds a program from instructions generated elsewhere, without going through the parser or the optimizer.
+    /// The addresses in `LoopHead` and `LoopEnd` are ignored and bound to the partner brackets again, so any value will do.
+    /// There is no source, so `source_map` maps every instruction to the range `index..index + 1`.
+    pub fn from_instructions(instructions: Vec<Instruction>, arithmetic: CellArithmetic) -> Result<Program, BindError> {
+        let mut depth = 0usize;
+        let mut outermost_loop_head = None;
+        for (index, instruction) in instructions.iter().enumerate() {
+            match instruction {
+                Instruction::LoopHead(_) => {
+                    if depth == 0 {
+                        outermost_loop_head = Some(index);
+                    }
+                    depth += 1;
+                }
+                Instruction::LoopEnd(_) if depth == 0 => return Err(BindError::UnexpectedLoopEnd { index }),
+                Instruction::LoopEnd(_) => depth -= 1,
+                _ => {}
+            }
+        }
+        if let (true, Some(index)) = (depth > 0, outermost_loop_head) {
+            return Err(BindError::UnclosedLoopHead { index });
+        }
+
+        let op_code_count = instructions.len();
+        let source_map = (0..op_code_count).map(|index| index..index + 1).collect();
+        let unbound_instructions = instructions
+            .into_iter()
+            .map(|instruction| instruction.map_address(|_| Unbound))
+            .collect();
+        let (instructions, loop_depths) = Self::bind_loops(unbound_instructions);
+
+        Ok(Program {
+            instructions,
+            source_map,
+            loop_depths,
+            op_code_count,
+            arithmetic,
+            parsed_from: None,
+        })
+    }
+
+    pub fn instructions(&self) -> &[Instruction] {
+        &self.instructions
+    }
+
+    /// Number of brainfuck commands in the source. Compare with `instructions().len()` to see how much the optimizer saved.
+    pub fn op_code_count(&self) -> usize {
+        self.op_code_count
+    }
+
+    /// The arithmetic chosen with `ParseOptions::arithmetic`
+    pub fn arithmetic(&self) -> CellArithmetic {
+        self.arithmetic
+    }
+
+    /// For every instruction, the range of bytes in the source it was lowered from.
+    /// Instructions that were combined by the optimizer span all of their source characters.
+    pub fn source_map(&self) -> &[Range<usize>] {
+        &self.source_map
+    }
+
+    /// For every instruction, the number of loops it is nested in: 0 at the top level and 1 inside a single loop.
+    /// The brackets of a loop are at the depth of the code around it, like `[` and `]` in `+[-]` are at depth 0 and `-` at depth 1.
+    pub fn loop_depths(&self) -> &[usize] {
+        &self.loop_depths
+    }
+
+    /// Renders the instructions one per line, prefixed with their index and indented by their loop depth.
+    pub fn disassemble(&self) -> String {
+        let width = self.instructions.len().to_string().len();
+
+        self.instructions
+            .iter()
+            .zip(&self.loop_depths)
+            .enumerate()
+            .map(|(index, (instruction, &depth))| {
+                format!("{:>width$}: {}{}\n", index, "  ".repeat(depth), instruction, width = width)
+            })
+            .collect()
+    }
+
+    /// Filters the op codes out of the source and remembers where each of them was found, one at a time.
+    /// Byte offsets are counted as if the chars were encoded as UTF-8, like in a `&str`.
+    fn lex<I: Iterator<Item = char>>(chars: I, options: &ParseOptions) -> impl Iterator<Item = (OpCode, SourceSpan)> {
+        let options = *options;
+
+        let mut line = 1;
+        let mut column = 1;
+        let mut byte_offset = 0;
+        let mut chars = chars.peekable();
+        std::iter::from_fn(move || loop {
+            let c = chars.next()?;
+            let span = SourceSpan { line, column, byte_offset };
 
-        for c in op_codes {
+            let op_code = if c == '@' && options.marks {
+                let mut label = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_alphanumeric() || **c == '_') {
+                    label.push(c);
+                    column += 1;
+                    byte_offset += c.len_utf8();
+                    chars.next();
+                }
+                Some(label).filter(|label| !label.is_empty()).map(OpCode::Mark)
+            } else {
+                OpCode::parse(c, &options)
+            };
+
+            if c == '\n' {
+                line += 1;
+                column = 1;
+            } else {
+                column += 1;
+            }
+            byte_offset += c.len_utf8();
+
+            if let Some(op_code) = op_code {
+                return Some((op_code, span));
+            }
+        })
+    }
+
+    fn check(op_codes: &[OpCode], spans: &[SourceSpan], options: &ParseOptions) -> Result<(), ParseError> {
+        Self::check_balanced_brackets(op_codes, spans, options.max_loop_depth)
+    }
+
+    /// Also rejects loops nested deeper than `max_loop_depth`, the unclosed loop heads are the loops around the current op code
+    fn check_balanced_brackets(op_codes: &[OpCode], spans: &[SourceSpan], max_loop_depth: Option<usize>) -> Result<(), ParseError> {
+        let mut unclosed_loop_heads = Vec::<usize>::new();
+
+        for (index, c) in op_codes.iter().enumerate() {
             match c {
-                OpCode::LoopHead => unclosed_loop_heads += 1,
-                OpCode::LoopEnd => {
-                    unclosed_loop_heads -= 1;
-                    if unclosed_loop_heads < 0 {
-                        return false;
+                OpCode::LoopHead => {
+                    unclosed_loop_heads.push(index);
+                    let depth = unclosed_loop_heads.len();
+                    if max_loop_depth.is_some_and(|max_loop_depth| depth > max_loop_depth) {
+                        return Err(ParseError::LoopNestingTooDeep { depth, span: spans[index] });
                     }
                 }
+                OpCode::LoopEnd => {
+                    unclosed_loop_heads
+                        .pop()
+                        .ok_or(ParseError::UnexpectedLoopEnd { span: spans[index] })?;
+                }
                 _ => {}
             }
         }
 
-        unclosed_loop_heads == 0
+        match unclosed_loop_heads.first() {
+            // Report the outermost loop head that was never closed
+            Some(&index) => Err(ParseError::UnclosedLoopHead { span: spans[index] }),
+            None => Ok(()),
+        }
     }
 
-    fn bind(op_codes: &[OpCode]) -> Vec<Instruction> {
+    fn bind(
+        op_codes: &[OpCode],
+        spans: &[SourceSpan],
+        options: &ParseOptions,
+    ) -> (Vec<Instruction>, Vec<Range<usize>>, Vec<usize>) {
         // In the bind step, we don't only bind the loop heads/ends, we also compress the OpCodes by optimizing them:
         // For interpreting brainfuck, we can pull off a simple optimization:
         // Occurrences in the form of "++++" can be compressed into a single instruction (that's why we have the usize in the Instruction enum)
         // It essentially boils down to run-length-encoding of increment/decrement instructions
 
-        let optimized_instructions: Vec<Instruction> = op_codes
+        let cell_size = options.cell_size();
+        let fold = |code: &OpCode, n: usize| match (code, cell_size) {
+            (OpCode::IncrementValue | OpCode::DecrementValue, Some(cell_size)) => n % cell_size,
+            _ => n,
+        };
+
+        // All op codes but marks are single byte characters
+        let source_range = |code: &OpCode, span: &SourceSpan| match code {
+            OpCode::Mark(label) => span.byte_offset..span.byte_offset + 1 + label.len(),
+            _ => span.byte_offset..span.byte_offset + 1,
+        };
+
+        if options.unoptimized {
+            let (unbound_instructions, source_map) = op_codes
+                .iter()
+                .zip(spans)
+                .map(|(code, span)| (code.as_instruction(), source_range(code, span)))
+                .unzip();
+            let (bound_instructions, loop_depths) = Self::bind_loops(unbound_instructions);
+            return (bound_instructions, source_map, loop_depths);
+        }
+
+        let optimized_instructions: Vec<(Instruction<Unbound>, Range<usize>)> = op_codes
             .iter()
-            .group_by(|c| *c)
+            .zip(spans)
+            .group_by(|(c, _)| *c)
             .into_iter()
-            .flat_map(|(&code, group)| match group.count() {
-                1 => vec![code.as_instruction()],
-                n => {
-                    if code.is_run_length_optimizable() {
-                        vec![code.create_optimized_instruction(n)]
-                    } else {
-                        vec![code.as_instruction(); n]
+            .flat_map(|(code, group)| {
+                let group: Vec<&SourceSpan> = group.map(|(_, span)| span).collect();
+                match group.len() {
+                    1 => vec![(code.as_instruction(), source_range(code, group[0]))],
+                    n => {
+                        if code.is_run_length_optimizable() {
+                            let range = group[0].byte_offset..group[n - 1].byte_offset + 1;
+                            match fold(code, n) {
+                                0 => vec![],
+                                1 => vec![(code.as_instruction(), range)],
+                                n => vec![(code.create_optimized_instruction(n), range)],
+                            }
+                        } else {
+                            group.iter().map(|span| (code.as_instruction(), source_range(code, span))).collect()
+                        }
                     }
                 }
             })
             .collect();
 
-        let mut loop_head_address_stack = Vec::<usize>::new();
+        let (unbound_instructions, source_map): (Vec<Instruction<Unbound>>, Vec<Range<usize>>) =
+            optimizer::optimize(optimized_instructions, options).into_iter().unzip();
+        let (bound_instructions, loop_depths) = Self::bind_loops(unbound_instructions);
 
-        let mut bound_instructions = optimized_instructions.to_vec();
-        for (current_index, instruction) in optimized_instructions.iter().enumerate() {
+        (bound_instructions, source_map, loop_depths)
+    }
+
+    /// Points every loop bracket at its partner and counts the loops around each instruction.
+    /// The brackets must be balanced.
+    fn bind_loops(unbound_instructions: Vec<Instruction<Unbound>>) -> (Vec<Instruction>, Vec<usize>) {
+        // Find the partner of every loop bracket first, loop ends are matched as soon as they are reached
+        let mut loop_head_address_stack = Vec::<usize>::new();
+        let mut loop_depths = Vec::with_capacity(unbound_instructions.len());
+        let mut partners = vec![0; unbound_instructions.len()];
+        for (current_index, instruction) in unbound_instructions.iter().enumerate() {
             match instruction {
-                Instruction::LoopHead(_) => loop_head_address_stack.push(current_index),
+                Instruction::LoopHead(_) => {
+                    loop_depths.push(loop_head_address_stack.len());
+                    loop_head_address_stack.push(current_index);
+                }
                 Instruction::LoopEnd(_) => {
                     let corresponding_start_index = loop_head_address_stack.pop().unwrap();
-
-                    // Set loop start address to the last loop head
-                    bound_instructions[current_index] = Instruction::LoopEnd(corresponding_start_index);
-
-                    // Set the loop end address of the start element to this address
-                    bound_instructions[corresponding_start_index] = Instruction::LoopHead(current_index);
+                    partners[current_index] = corresponding_start_index;
+                    partners[corresponding_start_index] = current_index;
+                    loop_depths.push(loop_head_address_stack.len());
                 }
-                _ => {}
+                _ => loop_depths.push(loop_head_address_stack.len()),
             }
         }
 
         assert!(loop_head_address_stack.is_empty());
 
-/*
-        println!(
-            "optimized_instructions ({:?} -> {:?})",
-            op_codes.len(),
-            bound_instructions.len()
+        let bound_instructions = unbound_instructions
+            .into_iter()
+            .zip(partners)
+            .map(|(instruction, partner)| instruction.map_address(|Unbound| partner))
+            .collect();
+
+        (bound_instructions, loop_depths)
+    }
+
+    /// Runs the program on stdin/stdout. `memory` can be any `Cell` type, `u8` being the common one.
+    pub fn run<C: Cell>(&self, memory: &mut [C]) -> Result<Halt, RuntimeError> {
+        self.run_with_options(memory, &ExecutionOptions::default())
+    }
+
+    /// Same as `run`, but also returns where the data pointer ended up, for programs that leave their result on the tape.
+    pub fn run_with_result<C: Cell>(&self, memory: &mut [C]) -> Result<RunResult, RuntimeError> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        let mut final_pointer = 0;
+        let halt = self.run_io_at(memory, &mut final_pointer, stdin.lock(), stdout.lock(), &ExecutionOptions::default())?;
+        Ok(RunResult { halt, final_pointer })
+    }
+
+    /// Runs the program on stdin/stdout on a tape left over from a previous run, without allocating.
+    /// The program starts at cell 0 but sees the cells as the caller left them, call `clear_memory` first for a fresh tape.
+    /// The only thing the optimizer assumes about the tape is that cell 0 is zero, which removes a loop at the very start of the
+    /// program as dead, so parse it with `ParseOptions::fragment` if that cell may be non-zero.
+    pub fn run_reusing<C: Cell>(&self, memory: &mut [C]) -> Result<Halt, RuntimeError> {
+        self.run(memory)
+    }
+
+    /// Runs the program on stdin/stdout on a fresh tape of `capacity` cells starting with the cells of `initial`, the rest is zero.
+    /// This feeds data to programs that work on the tape instead of reading it with `,`. The report contains the final tape.
+    /// Like with `run_reusing`, the only thing the optimizer assumes about the tape is that cell 0 is zero, so parse the program with
+    /// `ParseOptions::fragment` if `initial` starts with a non-zero cell. Panics if `initial` is longer than `capacity`.
+    pub fn run_with_initial<C: Cell>(&self, initial: &[C], capacity: usize) -> Result<ExecutionReport<C>, RuntimeError> {
+        assert!(
+            initial.len() <= capacity,
+            "The initial tape of {} cells does not fit into {} cells",
+            initial.len(),
+            capacity
         );
-*/
+        let mut memory = vec![C::default(); capacity];
+        memory[..initial.len()].copy_from_slice(initial);
 
-        bound_instructions
+        let halt = self.run(&mut memory)?;
+        Ok(ExecutionReport {
+            halt,
+            memory,
+            origin: 0,
+            stats: None,
+        })
     }
 
-    pub fn run(&self, memory: &mut [u8]) {
+    pub fn run_with_options<C: Cell>(&self, memory: &mut [C], options: &ExecutionOptions) -> Result<Halt, RuntimeError> {
         let stdin = io::stdin();
-        let mut stdin_bytes = stdin.lock().bytes();
+        let stdout = io::stdout();
+        self.run_io_with_options(memory, stdin.lock(), stdout.lock(), options)
+    }
 
-        let mut instruction_pointer: isize = 0;
-        let mut data_pointer: usize = 0;
-        while 0 <= instruction_pointer && instruction_pointer < self.instructions.len() as isize {
-            // casting to isize :/
+    /// Runs the program reading `,` from `input` and writing `.` to `output`.
+    pub fn run_io<C: Cell, R: Read, W: Write>(
+        &self,
+        memory: &mut [C],
+        input: R,
+        output: W,
+    ) -> Result<Halt, RuntimeError> {
+        self.run_io_with_options(memory, input, output, &ExecutionOptions::default())
+    }
 
-            let current_instruction = self.instructions[instruction_pointer as usize];
+    pub fn run_io_with_options<C: Cell, R: Read, W: Write>(
+        &self,
+        memory: &mut [C],
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+    ) -> Result<Halt, RuntimeError> {
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        Self::drive(&mut interpreter, input, output, |_, _| {})
+    }
+
+    /// Compiles the program to machine code and runs it on stdin/stdout, which is a lot faster than interpreting it for long running programs.
+    #[cfg(feature = "jit")]
+    pub fn jit_run(&self, memory: &mut [u8]) -> Result<Halt, RuntimeError> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.jit_run_io_with_options(memory, stdin.lock(), stdout.lock(), &ExecutionOptions::default())
+    }
 
-            match current_instruction {
-                Instruction::IncrementPointer(n) => {
-                    data_pointer += n;
-                    Self::panic_if_overflow(data_pointer, memory);
+    /// Same as `run_io_with_options`, but compiled to machine code. `ExecutionOptions::input_length`, `ExecutionOptions::max_steps`, `ExecutionOptions::max_output`, `ExecutionOptions::timeout` and `ExecutionOptions::interrupt` are not supported and ignored.
+    /// Panics if the program was parsed for saturating arithmetic. A tape without cells is a `RuntimeError::PointerOverflow` at 0,
+    /// unless the program is empty.
+    #[cfg(feature = "jit")]
+    pub fn jit_run_io_with_options<R: Read, W: Write>(
+        &self,
+        memory: &mut [u8],
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+    ) -> Result<Halt, RuntimeError> {
+        assert_eq!(self.arithmetic, CellArithmetic::Wrapping, "The JIT only supports wrapping arithmetic");
+        jit::run(&self.instructions, memory, input, output, options)
+    }
+
+    /// Runs the program without `std::io`, e.g. for hosts without a terminal like WebAssembly.
+    /// `input` is called for every `,` and returns `None` at the end of input, `output` receives every byte written by `.`.
+    pub fn run_with_callbacks<C: Cell, I: FnMut() -> Option<u8>, O: FnMut(u8)>(
+        &self,
+        memory: &mut [C],
+        options: &ExecutionOptions,
+        input: I,
+        output: O,
+    ) -> Result<Halt, RuntimeError> {
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        Self::drive_callbacks(&mut interpreter, input, output)
+    }
+
+    /// Measures how fast the interpreter runs the program, without the overhead of I/O: `,` reads zeros and the output of `.`
+    /// is discarded. Programs waiting for the end of input never end, limit them with `ExecutionOptions::max_steps` or `timeout`.
+    pub fn bench_run<C: Cell>(&self, memory: &mut [C], options: &ExecutionOptions) -> Result<BenchReport, RuntimeError> {
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        let start = Instant::now();
+        let halt = Self::drive_callbacks(&mut interpreter, || Some(0), |_| {})?;
+
+        Ok(BenchReport {
+            halt,
+            elapsed: start.elapsed(),
+            steps: interpreter.steps(),
+        })
+    }
 
-                    instruction_pointer += 1;
+    /// Runs the program `iterations` times like `bench_run`, each time on a tape of `capacity` zeroed cells, and returns the total time the runs took.
+    /// Clearing the tape between the runs is not measured. Stops at the first run that fails.
+    pub fn run_repeated<C: Cell>(&self, capacity: usize, iterations: usize) -> Result<Duration, RuntimeError> {
+        let mut memory = vec![C::default(); capacity];
+        let mut elapsed = Duration::ZERO;
+        for _ in 0..iterations {
+            clear_memory(&mut memory);
+            elapsed += self.bench_run(&mut memory, &ExecutionOptions::default())?.elapsed;
+        }
+        Ok(elapsed)
+    }
+
+    /// Takes the bytes of a `.` from the limit of `ExecutionOptions::max_output`.
+    /// Returns the bytes that still fit as an error if they are not all of them, then the output limit is reached.
+    fn limit_output<'b>(remaining: &mut Option<u64>, bytes: &'b [u8]) -> Result<&'b [u8], &'b [u8]> {
+        match remaining {
+            Some(remaining) if bytes.len() as u64 > *remaining => {
+                let fitting = &bytes[..*remaining as usize];
+                *remaining = 0;
+                Err(fitting)
+            }
+            Some(remaining) => {
+                *remaining -= bytes.len() as u64;
+                Ok(bytes)
+            }
+            None => Ok(bytes),
+        }
+    }
+
+    fn drive_callbacks<C: Cell, I: FnMut() -> Option<u8>, O: FnMut(u8)>(
+        interpreter: &mut Interpreter<C>,
+        mut input: I,
+        mut output: O,
+    ) -> Result<Halt, RuntimeError> {
+        let mut remaining_output = interpreter.options().max_output;
+        loop {
+            match interpreter.step()? {
+                StepResult::Continue | StepResult::Breakpoint | StepResult::DumpTape => {}
+                StepResult::Output(byte) => {
+                    let decimal;
+                    let bytes = match interpreter.options().output_encoding {
+                        OutputEncoding::Byte => slice::from_ref(&byte),
+                        OutputEncoding::Decimal => {
+                            let cell = interpreter.memory()[interpreter.data_pointer()];
+                            decimal = format!("{} ", interpreter.options().signedness.render(cell));
+                            decimal.as_bytes()
+                        }
+                    };
+                    match Self::limit_output(&mut remaining_output, bytes) {
+                        Ok(bytes) => bytes.iter().copied().for_each(&mut output),
+                        Err(bytes) => {
+                            bytes.iter().copied().for_each(&mut output);
+                            return Ok(Halt::OutputLimitReached);
+                        }
+                    }
                 }
-                Instruction::DecrementPointer(n) => {
-                    // TODO: This is ugly, there must be a better way
-                    let next_value = (data_pointer as isize) - (n as isize);
-                    Self::panic_if_underflow(next_value);
-                    data_pointer = next_value as usize;
+                StepResult::NeedsInput => match input() {
+                    Some(byte) => interpreter.provide_input(byte),
+                    None => interpreter.provide_eof(),
+                },
+                StepResult::Halted(halt) => return Ok(halt),
+            }
+        }
+    }
 
-                    instruction_pointer += 1;
+    /// Same as `run_io_with_options`, but starts at `data_pointer` instead of cell 0 and stores where the data pointer ended up in it,
+    /// also if the program fails. This runs several programs on the same tape like one, if they are parsed with `ParseOptions::fragment`.
+    pub fn run_io_at<C: Cell, R: Read, W: Write>(
+        &self,
+        memory: &mut [C],
+        data_pointer: &mut usize,
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+    ) -> Result<Halt, RuntimeError> {
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        interpreter.set_data_pointer(*data_pointer);
+        let result = Self::drive(&mut interpreter, input, output, |_, _| {});
+        *data_pointer = interpreter.data_pointer();
+        result
+    }
+
+    /// Runs the program with `input` for `,` and returns everything written by `.` instead of printing it.
+    pub fn run_to_string<C: Cell>(&self, memory: &mut [C], input: &[u8]) -> Result<Vec<u8>, RuntimeError> {
+        let mut output = Vec::new();
+        self.run_io(memory, input, &mut output)?;
+        Ok(output)
+    }
+
+    /// Runs the program with `input` for `,` and returns where the data pointer stopped and the non-zero cells of `memory`.
+    /// The output of `.` is discarded.
+    pub fn run_to_state<C: Cell>(&self, memory: &mut [C], input: &[u8]) -> Result<TapeState, RuntimeError> {
+        let mut data_pointer = 0;
+        self.run_io_at(memory, &mut data_pointer, input, io::sink(), &ExecutionOptions::default())?;
+        Ok(TapeState::new(memory, data_pointer))
+    }
+
+    /// Runs the program once lowered again with `ParseOptions::unoptimized` and once optimized, each with `input` for `,` on its
+    /// own tape, and panics if they behave differently, to find bugs in the optimizer. Both tapes should start with the same cells.
+    /// Same as `run_both_and_compare_with_options` with the default `ExecutionOptions`.
+    pub fn run_both_and_compare<C: Cell>(
+        &self,
+        memory_unoptimized: &mut [C],
+        memory_optimized: &mut [C],
+        input: &[u8],
+    ) -> Result<Halt, RuntimeError> {
+        self.run_both_and_compare_with_options(memory_unoptimized, memory_optimized, input, &ExecutionOptions::default())
+    }
+
+    /// Same as `run_both_and_compare`, but runs both programs with `options`.
+    /// If the unoptimized program finishes, the optimized one has to finish with the same output and the same tape. Otherwise
+    /// only the output is compared, as far as both programs got: the optimized one executes fewer instructions, so it may get
+    /// further within a step limit, and it may not fail where the unoptimized one did, e.g. if `<>` moved before cell 0.
+    /// Returns how the unoptimized program stopped, which tells whether the tapes were compared.
+    /// Panics if the program was built with `from_instructions`, which leaves nothing to lower again.
+    pub fn run_both_and_compare_with_options<C: Cell>(
+        &self,
+        memory_unoptimized: &mut [C],
+        memory_optimized: &mut [C],
+        input: &[u8],
+        options: &ExecutionOptions,
+    ) -> Result<Halt, RuntimeError> {
+        assert_eq!(
+            memory_unoptimized.len(),
+            memory_optimized.len(),
+            "Both programs need tapes of the same length"
+        );
+        let (op_codes, parse_options) = self
+            .parsed_from
+            .as_ref()
+            .expect("Only parsed programs can be lowered without optimizing");
+
+        let run = |unoptimized: bool, memory: &mut [C]| {
+            let program = Self::from_op_codes(op_codes.clone(), &ParseOptions { unoptimized, ..*parse_options })
+                .expect("The op codes were parsed with the same options before");
+            let mut data_pointer = 0;
+            let mut output = Vec::new();
+            let result = program.run_io_at(memory, &mut data_pointer, input, &mut output, options);
+            (result, output, data_pointer)
+        };
+        let (unoptimized_result, unoptimized_output, unoptimized_data_pointer) = run(true, memory_unoptimized);
+        let (optimized_result, optimized_output, optimized_data_pointer) = run(false, memory_optimized);
+
+        if let Ok(Halt::Finished) = unoptimized_result {
+            match optimized_result {
+                Ok(halt) => assert_eq!(Halt::Finished, halt, "Only the unoptimized program finished"),
+                Err(error) => panic!("Only the optimized program failed: {}", error),
+            }
+            assert_eq!(unoptimized_output, optimized_output, "The programs wrote different output");
+            assert_eq!(unoptimized_data_pointer, optimized_data_pointer, "The data pointers stopped at different cells");
+            assert!(memory_unoptimized == memory_optimized, "The programs left different tapes");
+        } else {
+            let length = unoptimized_output.len().min(optimized_output.len());
+            assert_eq!(
+                unoptimized_output[..length],
+                optimized_output[..length],
+                "The programs wrote different output before one of them stopped"
+            );
+        }
+        unoptimized_result
+    }
+
+    /// Runs the program with `input` for `,` and returns the 64 bit FNV-1a hash of everything written by `.` instead of printing it.
+    /// The hash only depends on the output bytes, so it can be compared across platforms without storing the whole output.
+    pub fn run_hash<C: Cell>(&self, memory: &mut [C], input: &[u8]) -> Result<u64, RuntimeError> {
+        let mut output = Fnv1aWriter::new();
+        self.run_io(memory, input, &mut output)?;
+        Ok(output.hash)
+    }
+
+    /// Same as `run_io_with_options`, but calls `on_breakpoint` with the data pointer and the tape whenever a `#` is executed.
+    /// The program has to be parsed with `ParseOptions::breakpoints` for `#` to be recognized.
+    pub fn run_io_with_breakpoints<C: Cell, R: Read, W: Write, F: FnMut(usize, &[C])>(
+        &self,
+        memory: &mut [C],
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+        mut on_breakpoint: F,
+    ) -> Result<Halt, RuntimeError> {
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        Self::drive(&mut interpreter, input, output, |interpreter, executed| {
+            if let Instruction::Breakpoint = self.instructions[executed] {
+                on_breakpoint(interpreter.data_pointer(), interpreter.memory());
+            }
+        })
+    }
+
+    /// Same as `run_io_with_options`, but calls `on_mark` with the label, the data pointer and the tape whenever an `@label` is reached.
+    /// The program has to be parsed with `ParseOptions::marks` for labels to be recognized.
+    pub fn run_io_with_marks<C: Cell, R: Read, W: Write, F: FnMut(&str, usize, &[C])>(
+        &self,
+        memory: &mut [C],
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+        mut on_mark: F,
+    ) -> Result<Halt, RuntimeError> {
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        Self::drive(&mut interpreter, input, output, |interpreter, executed| {
+            if let Instruction::Mark(ref label) = self.instructions[executed] {
+                on_mark(label, interpreter.data_pointer(), interpreter.memory());
+            }
+        })
+    }
+
+    /// Same as `run_io_with_options`, but calls `on_step` after every executed instruction
+    /// with its address, the instruction, the data pointer and the tape as the instruction left them.
+    pub fn run_io_with_trace<C: Cell, R: Read, W: Write, F: FnMut(usize, &Instruction, usize, &[C])>(
+        &self,
+        memory: &mut [C],
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+        mut on_step: F,
+    ) -> Result<Halt, RuntimeError> {
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        Self::drive(&mut interpreter, input, output, |interpreter, executed| {
+            on_step(executed, &self.instructions[executed], interpreter.data_pointer(), interpreter.memory());
+        })
+    }
+
+    /// Same as `run_io_with_options`, but reports suspicious patterns to `on_lint` as a teaching aid, see `RuntimeLint`.
+    /// A cell counts as written once the program changed it or read input into it. Testing it with a loop, printing it or
+    /// multiplying it before is reported once per cell. Scans are not reported, as finding an untouched zero is what they are for.
+    pub fn run_io_with_lints<C: Cell, R: Read, W: Write, F: FnMut(RuntimeLint)>(
+        &self,
+        memory: &mut [C],
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+        mut on_lint: F,
+    ) -> Result<Halt, RuntimeError> {
+        // Cells that were written or already reported
+        let mut written = vec![false; memory.len()];
+        let length = memory.len() as isize;
+        let wrap = options.tape_mode == TapeMode::Wrap;
+        let mut data_pointer = 0;
+
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        Self::drive(&mut interpreter, input, output, |interpreter, executed| {
+            let instruction = &self.instructions[executed];
+            // The cell is read before the instruction moves the pointer
+            if let Instruction::LoopHead(_)
+            | Instruction::LoopEnd(_)
+            | Instruction::PutChar(_)
+            | Instruction::MultiplyAdd(_)
+            | Instruction::CopyTo { .. } = instruction
+            {
+                if !written[data_pointer] {
+                    written[data_pointer] = true;
+                    on_lint(RuntimeLint::UninitializedRead {
+                        address: executed,
+                        position: data_pointer,
+                    });
                 }
-                Instruction::IncrementValue(n) => {
-                    memory[data_pointer] = ((memory[data_pointer] as usize) + n) as u8;
+            }
 
-                    instruction_pointer += 1;
+            let moved_data_pointer = interpreter.data_pointer();
+            if let Instruction::DecrementPointer(_) | Instruction::ScanLeft(_) = instruction {
+                // Only a wrapping tape lets the pointer move left of the first cell, it continues at the end
+                if moved_data_pointer > data_pointer {
+                    on_lint(RuntimeLint::PointerBelowStart { address: executed });
                 }
-                Instruction::DecrementValue(n) => {
-                    memory[data_pointer] = ((memory[data_pointer] as usize) - n) as u8;
+            }
 
-                    instruction_pointer += 1;
+            // Marks the cell `offset` cells away from the moved data pointer as written
+            let mut write = |offset: isize| {
+                let position = moved_data_pointer as isize + offset;
+                let position = if wrap { position.rem_euclid(length) } else { position };
+                if (0..length).contains(&position) {
+                    written[position as usize] = true;
                 }
-                Instruction::LoopHead(loop_end_address) => {
-                    if memory[data_pointer] == 0 {
-                        instruction_pointer = (loop_end_address as isize) + 1;
-                    } else {
-                        instruction_pointer += 1;
-                    }
+            };
+            match *instruction {
+                Instruction::IncrementValue(_)
+                | Instruction::DecrementValue(_)
+                | Instruction::SetZero
+                | Instruction::SetValue(_)
+                | Instruction::GetChar(_) => write(0),
+                Instruction::AddAtOffset { offset, .. } => write(offset),
+                // The pointer ends on the last zeroed cell
+                Instruction::FillZero { count } => (0..count as isize).for_each(|offset| write(-offset)),
+                Instruction::MultiplyAdd(ref targets) => targets.iter().for_each(|&(offset, _)| write(offset)),
+                Instruction::CopyTo { ref targets } => targets.iter().for_each(|&offset| write(offset)),
+                _ => {}
+            }
+            data_pointer = moved_data_pointer;
+        })
+    }
+
+    /// Runs the program on stdin/stdout with a tape that grows as needed instead of a fixed capacity.
+    /// Returns the final tape, its length is the capacity it grew to.
+    pub fn run_growable<C: Cell>(&self) -> Result<Vec<C>, RuntimeError> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        let (_, memory) = self.run_io_growable(stdin.lock(), stdout.lock(), &ExecutionOptions::default())?;
+        Ok(memory)
+    }
+
+    pub fn run_io_growable<C: Cell, R: Read, W: Write>(
+        &self,
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+    ) -> Result<(Halt, Vec<C>), RuntimeError> {
+        let mut interpreter = Interpreter::growable(self, *options);
+        let halt = Self::drive(&mut interpreter, input, output, |_, _| {})?;
+        Ok((halt, interpreter.into_memory().unwrap()))
+    }
+
+    /// Same as `run_io_with_options`, but also counts what was executed.
+    pub fn run_io_with_stats<C: Cell, R: Read, W: Write>(
+        &self,
+        memory: &mut [C],
+        input: R,
+        output: W,
+        options: &ExecutionOptions,
+    ) -> Result<(Halt, ExecutionStats), RuntimeError> {
+        let mut interpreter = Interpreter::with_options(self, memory, *options);
+        self.drive_with_stats(&mut interpreter, input, output)
+    }
+
+    /// Runs the program as configured by `execution`, e.g.
+    /// `program.execute::<u8, _, _>(Execution::new().memory_capacity(30000).max_steps(1000))`.
+    pub fn execute<C: Cell, R: Read, W: Write>(
+        &self,
+        execution: Execution<R, W>,
+    ) -> Result<ExecutionReport<C>, RuntimeError> {
+        let Execution {
+            options,
+            memory_capacity,
+            collect_stats,
+            input,
+            output,
+        } = execution;
+
+        let mut memory = match memory_capacity {
+            Some(capacity) => vec![C::default(); capacity],
+            None => Vec::new(),
+        };
+        let mut interpreter = match memory_capacity {
+            Some(_) => Interpreter::with_options(self, &mut memory, options),
+            None => Interpreter::growable(self, options),
+        };
+
+        let (halt, stats) = if collect_stats {
+            let (halt, stats) = self.drive_with_stats(&mut interpreter, input, output)?;
+            (halt, Some(stats))
+        } else {
+            (Self::drive(&mut interpreter, input, output, |_, _| {})?, None)
+        };
+
+        let origin = interpreter.origin();
+        if let Some(grown) = interpreter.into_memory() {
+            memory = grown;
+        }
+
+        Ok(ExecutionReport {
+            halt,
+            memory,
+            origin,
+            stats,
+        })
+    }
+
+    fn drive_with_stats<C: Cell, R: Read, W: Write>(
+        &self,
+        interpreter: &mut Interpreter<C>,
+        input: R,
+        output: W,
+    ) -> Result<(Halt, ExecutionStats), RuntimeError> {
+        // Counting per instruction and grouping by kind afterwards keeps the work per step minimal
+        let mut executions = vec![0u64; self.instructions.len()];
+        let mut loops = vec![LoopStats::default(); self.instructions.len()];
+        let mut stats = ExecutionStats::default();
+
+        let halt = Self::drive(interpreter, input, output, |interpreter, executed| {
+            executions[executed] += 1;
+            stats.peak_data_pointer = stats.peak_data_pointer.max(interpreter.data_pointer());
+
+            let fell_through = interpreter.instruction_pointer() == executed + 1;
+            match self.instructions[executed] {
+                Instruction::LoopHead(_) if fell_through => {
+                    stats.loop_iterations += 1;
+                    loops[executed].entries += 1;
+                    loops[executed].iterations += 1;
+                }
+                Instruction::LoopEnd(loop_head_address) if !fell_through => {
+                    stats.loop_iterations += 1;
+                    loops[loop_head_address].iterations += 1;
                 }
-                Instruction::LoopEnd(loop_start_address) => {
+                _ => {}
+            }
+        })?;
 
-                    if memory[data_pointer] == 0 {
-                        instruction_pointer += 1;
-                    } else {
-                        instruction_pointer = (loop_start_address as isize) + 1;
+        stats.steps = interpreter.steps();
+        for (instruction, count) in self.instructions.iter().zip(executions) {
+            if count > 0 {
+                *stats.instruction_counts.entry(instruction.name()).or_insert(0) += count;
+            }
+        }
+        stats.loops = loops
+            .into_iter()
+            .enumerate()
+            .filter(|(_, loop_stats)| loop_stats.entries > 0)
+            .collect();
+
+        Ok((halt, stats))
+    }
+
+    /// Steps the interpreter until it halts, connecting it to `input` and `output`.
+    /// `on_step` is called after every executed instruction with the address of that instruction.
+    fn drive<C: Cell, R: Read, W: Write, F: FnMut(&Interpreter<C>, usize)>(
+        interpreter: &mut Interpreter<C>,
+        input: R,
+        mut output: W,
+        mut on_step: F,
+    ) -> Result<Halt, RuntimeError> {
+        let mut input_bytes = io::BufReader::new(input).bytes();
+        let mut remaining_output = interpreter.options().max_output;
+
+        let halt = loop {
+            let instruction_pointer = interpreter.instruction_pointer();
+            match interpreter.step()? {
+                StepResult::Continue | StepResult::Breakpoint => {}
+                StepResult::DumpTape => {
+                    // Keep the dump after the output written before it
+                    output.flush()?;
+                    let memory = interpreter.memory();
+                    eprint!("{}", dump_tape_with_signedness(memory, interpreter.data_pointer(), 8, interpreter.options().signedness));
+                }
+                StepResult::Output(byte) => {
+                    let decimal;
+                    let bytes = match interpreter.options().output_encoding {
+                        OutputEncoding::Byte => slice::from_ref(&byte),
+                        // Wider cells are truncated in the byte, so the cell itself is written
+                        OutputEncoding::Decimal => {
+                            let cell = interpreter.memory()[interpreter.data_pointer()];
+                            decimal = format!("{} ", interpreter.options().signedness.render(cell));
+                            decimal.as_bytes()
+                        }
+                    };
+                    match Self::limit_output(&mut remaining_output, bytes) {
+                        Ok(bytes) => output.write_all(bytes)?,
+                        Err(bytes) => {
+                            output.write_all(bytes)?;
+                            break Halt::OutputLimitReached;
+                        }
                     }
                 }
-                Instruction::PutChar => {
-                    print!("{}", memory[data_pointer] as char);
+                StepResult::NeedsInput => {
+                    // Make sure a prompt is visible before blocking on input
+                    output.flush()?;
 
-                    instruction_pointer += 1;
-                },
-                Instruction::GetChar => {
-                    let input = stdin_bytes.next();
-                    if input.is_none() {
-                        continue;
+                    match input_bytes.next() {
+                        Some(input) => interpreter.provide_input(input?),
+                        None => interpreter.provide_eof(),
                     }
+                    continue;
+                }
+                StepResult::Halted(halt) => break halt,
+            }
+            on_step(interpreter, instruction_pointer);
+        };
 
-                    memory[data_pointer] = input.unwrap().unwrap();
+        output.flush()?;
+        Ok(halt)
+    }
+}
 
-                    instruction_pointer += 1;
-                },
+#[cfg(test)]
+mod tests {
+    use super::testing::{assert_same_as_unoptimized, run_with_options, unoptimized, wrap};
+    use super::*;
+
+    #[test]
+    fn instructions_render_compactly() {
+        let rendered = [
+            (Instruction::IncrementPointer(4), ">4"),
+            (Instruction::DecrementPointer(1), "<"),
+            (Instruction::IncrementValue(1), "+"),
+            (Instruction::DecrementValue(12), "-12"),
+            (Instruction::PutChar(3), ".3"),
+            (Instruction::GetChar(1), ","),
+            (Instruction::LoopHead(12), "[→12"),
+            (Instruction::LoopEnd(0), "]←0"),
+            (Instruction::SetZero, "=0"),
+            (Instruction::SetValue(-5), "=-5"),
+            (Instruction::FillZero { count: 3 }, "=0×3"),
+            (Instruction::MultiplyAdd(vec![(1, 2), (-3, -1)]), "*(+2@1 -1@-3)"),
+            (Instruction::CopyTo { targets: vec![1, 2] }, "*(@1 @2)"),
+            (Instruction::ScanRight(2), "[>2]"),
+            (Instruction::ScanLeft(1), "[<]"),
+            (Instruction::AddAtOffset { offset: -2, amount: 7 }, "+7@-2"),
+            (Instruction::Breakpoint, "#"),
+            (Instruction::DumpTape, "?"),
+            (Instruction::Mark("loop_1".to_string()), "@loop_1"),
+        ];
+        for (instruction, expected) in rendered {
+            assert_eq!(instruction.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn op_codes_render_as_their_command() {
+        let options = ParseOptions {
+            breakpoints: true,
+            dump_tape: true,
+            marks: true,
+            ..ParseOptions::default()
+        };
+        let code = "><+-.,[]#?@label";
+        assert_eq!(Program::op_codes(code, &options).iter().join(""), code);
+    }
+
+    #[test]
+    fn signed_cells_render_as_twos_complement() {
+        assert_eq!(Signedness::Signed.render(200u8), "-56");
+        assert_eq!(Signedness::Unsigned.render(200u8), "200");
+        assert_eq!(Signedness::Signed.render(127u8), "127");
+        assert_eq!(Signedness::Signed.render(u16::MAX), "-1");
+
+        let options = ExecutionOptions {
+            output_encoding: OutputEncoding::Decimal,
+            signedness: Signedness::Signed,
+            ..ExecutionOptions::default()
+        };
+        let (_, output, _) = run_with_options(&format!("{}.", "+".repeat(200)), &ParseOptions::default(), &options, b"");
+        assert_eq!(output, b"-56 ");
+    }
+
+    #[test]
+    fn programs_see_the_cells_they_are_given() {
+        let program = Program::parse("+>[-]<").unwrap();
+        assert_eq!(program.run_with_initial::<u8>(&[5, 7], 4).unwrap().memory, [6, 0, 0, 0]);
+
+        let mut memory = [5u8, 7];
+        program.run_reusing(&mut memory).unwrap();
+        assert_eq!(memory, [6, 0]);
+    }
+
+    #[test]
+    fn offsets_may_be_the_same_cell_of_a_short_wrapping_tape() {
+        for code in ["+>>[-]", "+>>[-]+<<+", "++>>>+<<[-]>>+", "[-]+>>+<<+>>>>+"] {
+            let run = |options: &ParseOptions| {
+                let mut memory = [0u8; 2];
+                let program = Program::parse_with_options(code, options).unwrap();
+                program.run_io_with_options(&mut memory, io::empty(), io::sink(), &wrap()).unwrap();
+                memory
+            };
+            assert_eq!(run(&ParseOptions::default()), run(&unoptimized()), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn loops_are_bound_to_their_partners() {
+        let code = format!("{}.{}+[>[-]<-]", "+[".repeat(500), "-]".repeat(500));
+        let program = Program::parse_with_options(&code, &unoptimized()).unwrap();
+
+        // Every command is its own instruction, so the brackets can be matched in the source
+        let mut heads = Vec::new();
+        let mut partners = vec![None; code.len()];
+        for (index, command) in code.bytes().enumerate() {
+            match command {
+                b'[' => heads.push(index),
+                b']' => {
+                    let head = heads.pop().unwrap();
+                    partners[head] = Some(index);
+                    partners[index] = Some(head);
+                }
+                _ => {}
             }
+        }
 
+        assert_eq!(program.instructions().len(), code.len());
+        for (index, instruction) in program.instructions().iter().enumerate() {
+            match instruction {
+                Instruction::LoopHead(partner) | Instruction::LoopEnd(partner) => assert_eq!(partners[index], Some(*partner)),
+                _ => assert_eq!(partners[index], None),
+            }
         }
+        assert_eq!(program.loop_depths()[500 * 2], 500);
+    }
+
+    #[test]
+    fn embedded_input_follows_the_first_exclamation_mark() {
+        assert_eq!(split_embedded_input(",[.,]"), (",[.,]", ""));
+        assert_eq!(split_embedded_input(",[.,]!"), (",[.,]", ""));
+        assert_eq!(split_embedded_input(",[.,]!ab!c"), (",[.,]", "ab!c"));
+        assert_eq!(split_embedded_input("Echo! ,[.,]!ab"), ("Echo", " ,[.,]!ab"));
+        assert_eq!(split_embedded_input(""), ("", ""));
     }
 
-    fn panic_if_overflow(data_pointer: usize, memory: &[u8]) {
-        if data_pointer >= memory.len() {
-            panic!("data pointer overflow");
+    #[test]
+    fn instructions_with_unbalanced_loops_are_rejected() {
+        use Instruction::{IncrementValue, LoopEnd, LoopHead};
+
+        let bind = |instructions: Vec<Instruction>| Program::from_instructions(instructions, CellArithmetic::Wrapping).map(|_| ());
+        assert_eq!(bind(vec![IncrementValue(1), LoopEnd(0)]), Err(BindError::UnexpectedLoopEnd { index: 1 }));
+        assert_eq!(bind(vec![LoopHead(0), LoopEnd(0), LoopEnd(0), LoopEnd(0)]), Err(BindError::UnexpectedLoopEnd { index: 2 }));
+        assert_eq!(bind(vec![LoopHead(0)]), Err(BindError::UnclosedLoopHead { index: 0 }));
+        // The outermost unclosed loop is reported, not the innermost one
+        assert_eq!(
+            bind(vec![LoopHead(0), LoopEnd(0), LoopHead(0), LoopHead(0), LoopEnd(0)]),
+            Err(BindError::UnclosedLoopHead { index: 2 })
+        );
+        assert_eq!(
+            BindError::UnclosedLoopHead { index: 2 }.to_string(),
+            "unbalanced brackets: loop head without a matching loop end at instruction 2"
+        );
+
+        // The addresses are bound again, whatever they were
+        let program = Program::from_instructions(vec![LoopHead(7), IncrementValue(1), LoopEnd(7)], CellArithmetic::Wrapping).unwrap();
+        assert_eq!(program.instructions(), [LoopHead(2), IncrementValue(1), LoopEnd(0)]);
+    }
+
+    #[test]
+    fn runs_of_changes_are_folded_modulo_the_cell_size() {
+        let byte_cells = ParseOptions {
+            cell_bits: Some(u8::BITS),
+            ..ParseOptions::default()
+        };
+        let program = Program::parse_with_options(&format!(",{}.", "+".repeat(256)), &byte_cells).unwrap();
+        assert_eq!(program.instructions(), [Instruction::GetChar(1), Instruction::PutChar(1)]);
+        let program = Program::parse_with_options(&format!(",{}.", "-".repeat(257)), &byte_cells).unwrap();
+        assert_eq!(program.instructions(), [Instruction::GetChar(1), Instruction::DecrementValue(1), Instruction::PutChar(1)]);
+        assert_eq!(program.run_to_string(&mut [0u8; 1], b"a").unwrap(), b"`");
+
+        // Wider cells would see the difference
+        let program = Program::parse(&format!(",{}.", "+".repeat(256))).unwrap();
+        assert_eq!(program.instructions()[1], Instruction::IncrementValue(256));
+        let mut memory = [0u16; 1];
+        program.run_to_string(&mut memory, b"a").unwrap();
+        assert_eq!(memory, [97 + 256]);
+    }
+
+    #[test]
+    fn sources_without_commands_parse_to_empty_programs() {
+        let comment = "Only a comment without a single command in it\n".repeat(20_000);
+        for code in ["", " \t\r\n\n  ", comment.as_str()] {
+            let program = Program::parse(code).unwrap();
+            assert_eq!(program.instructions(), []);
+            assert_eq!(program.op_code_count(), 0);
+            assert_eq!(program.run_io(&mut [0u8; 1], io::empty(), io::sink()).unwrap(), Halt::Finished);
+        }
+    }
+
+    #[test]
+    fn runs_of_output_are_a_single_instruction() {
+        let program = Program::parse(&format!("+{}", ".".repeat(1_000_000))).unwrap();
+        assert_eq!(program.instructions(), [Instruction::IncrementValue(1), Instruction::PutChar(1_000_000)]);
+        assert_eq!(program.run_to_string(&mut [0u8; 1], b"").unwrap(), [1; 1_000_000]);
+    }
+
+    #[test]
+    fn runs_of_output_stop_at_the_output_limit() {
+        for max_output in 0..=6 {
+            let options = ExecutionOptions {
+                max_output: Some(max_output),
+                ..ExecutionOptions::default()
+            };
+            assert_same_as_unoptimized(",..+...", &options, b"a");
+            assert_same_as_unoptimized(",..+...", &ExecutionOptions { max_steps: Some(max_output), ..options }, b"a");
         }
     }
-    fn panic_if_underflow(data_pointer: isize) {
-        if data_pointer < 0 {
-            panic!("data pointer underflow");
+
+    #[test]
+    fn runs_of_input_keep_the_last_byte() {
+        let program = Program::parse(",,,.").unwrap();
+        assert_eq!(program.instructions(), [Instruction::GetChar(3), Instruction::PutChar(1)]);
+        assert_eq!(program.run_to_string(&mut [0u8; 1], b"abcd").unwrap(), b"c");
+    }
+
+    #[test]
+    fn runs_of_input_stop_at_the_end_of_the_input() {
+        for eof_behavior in [EofBehavior::Unchanged, EofBehavior::Zero, EofBehavior::MinusOne] {
+            for input_length in [None, Some(0), Some(1), Some(2), Some(3), Some(5)] {
+                let options = ExecutionOptions {
+                    eof_behavior,
+                    input_length,
+                    ..ExecutionOptions::default()
+                };
+                for input in [&b""[..], b"a", b"ab", b"abcd"] {
+                    assert_same_as_unoptimized("+,,,.>,.,,.", &options, input);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn initial_cells_wrap_at_their_own_width() {
+        let program = Program::parse("->+>-").unwrap();
+        assert_eq!(program.run_with_initial::<u16>(&[0, u16::MAX], 3).unwrap().memory, [u16::MAX, 0, u16::MAX]);
+        assert_eq!(program.run_with_initial::<u32>(&[0, u32::MAX], 3).unwrap().memory, [u32::MAX, 0, u32::MAX]);
+
+        let program = Program::parse("+>+").unwrap();
+        assert_eq!(program.run_with_initial::<u16>(&[255, 65_534], 2).unwrap().memory, [256, u16::MAX]);
+        assert_eq!(program.run_with_initial::<u32>(&[255, 65_535], 2).unwrap().memory, [256, 65_536]);
+    }
+
+    /// A small xorshift generator, so the programs are the same in every run
+    struct Random(u64);
+
+    impl Random {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
         }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    /// A program with balanced brackets, favouring the patterns the optimizer looks for
+    fn random_program(random: &mut Random, depth: usize) -> String {
+        let mut code = String::new();
+        for _ in 0..1 + random.below(8) {
+            match random.below(10) {
+                0 | 1 if depth < 3 => {
+                    code.push('[');
+                    code.push_str(&random_program(random, depth + 1));
+                    code.push(']');
+                }
+                2 => code.push_str(["[-]", "[>]", "[<<]", "[->+<]", "[->>++<<]", "[-]+++"][random.below(6)]),
+                _ => {
+                    let command = b"+-<>.,"[random.below(6)] as char;
+                    code.extend(std::iter::repeat(command).take(1 + random.below(4)));
+                }
+            }
+        }
+        code
+    }
+
+    /// Compares the optimized and unoptimized runs of many random programs with `parse_options` and `execution_options`.
+    /// With `ParseOptions::fragment`, the tape starts with random cells, otherwise with random cells but a zero cell 0.
+    fn compare_random_programs<C: Cell>(parse_options: ParseOptions, execution_options: ExecutionOptions, length: usize) {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        let execution_options = ExecutionOptions {
+            eof_behavior: EofBehavior::Zero,
+            max_steps: Some(10_000),
+            ..execution_options
+        };
+        // Programs that fail or reach the step limit only have their output compared
+        let mut unfinished = 0;
+        // The pointer would mostly fall off a strict tape to the left if the programs started at cell 0
+        let start = match (execution_options.tape_mode, execution_options.address_space) {
+            (TapeMode::Strict, None) => ">".repeat(length / 2),
+            _ => String::new(),
+        };
+        for _ in 0..500 {
+            let code = start.clone() + &random_program(&mut random, 0);
+            let mut initial: Vec<C> = (0..length).map(|_| C::from_input_byte(random.next() as u8 % 4)).collect();
+            if !parse_options.fragment {
+                initial[0] = C::default();
+            }
+            let program =
+                Program::parse_with_options(&code, &parse_options).unwrap_or_else(|error| panic!("{:?} did not parse: {}", code, error));
+            let halt = program.run_both_and_compare_with_options(&mut initial.clone(), &mut initial, b"xyz", &execution_options);
+            if !matches!(halt, Ok(Halt::Finished)) {
+                unfinished += 1;
+            }
+        }
+        assert!(
+            unfinished < 250,
+            "Only {} of 500 programs finished with {:?} and {:?}",
+            500 - unfinished,
+            parse_options,
+            execution_options
+        );
+    }
+
+    fn compare_random_programs_in_all_modes<C: Cell>() {
+        for arithmetic in [CellArithmetic::Wrapping, CellArithmetic::Saturating] {
+            for fragment in [false, true] {
+                let parse_options = ParseOptions {
+                    arithmetic,
+                    fragment,
+                    ..ParseOptions::default()
+                };
+                compare_random_programs::<C>(parse_options, ExecutionOptions::default(), 32);
+                for length in 1..=4 {
+                    compare_random_programs::<C>(parse_options, wrap(), length);
+                }
+                let address_space = ExecutionOptions {
+                    address_space: NonZeroUsize::new(16),
+                    ..ExecutionOptions::default()
+                };
+                compare_random_programs::<C>(parse_options, address_space, 16);
+            }
+        }
+    }
+
+    #[test]
+    fn optimized_programs_behave_like_unoptimized_ones_on_u8_cells() {
+        compare_random_programs_in_all_modes::<u8>();
+    }
+
+    #[test]
+    fn optimized_programs_behave_like_unoptimized_ones_on_u16_cells() {
+        compare_random_programs_in_all_modes::<u16>();
+    }
+
+    #[test]
+    fn optimized_programs_behave_like_unoptimized_ones_on_u32_cells() {
+        compare_random_programs_in_all_modes::<u32>();
     }
 }
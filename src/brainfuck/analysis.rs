@@ -0,0 +1,270 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::ops::Range;
+
+use super::{CellArithmetic, Instruction, Program, Unbound};
+
+/// A warning about valid code that is likely a mistake, see `Program::lint`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Lint {
+    pub kind: LintKind,
+    /// The range of source bytes the warning is about, locate it with `SourceSpan::locate`
+    pub source: Range<usize>,
+}
+
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum LintKind {
+    /// A loop without commands like `[]`, which never ends once it is entered
+    EmptyLoop,
+    /// A loop that leaves the data pointer where it was and never changes the cell it tests, like `[>+<]`, or only increments it
+    /// with saturating arithmetic, like `[+]`. It never ends once it is entered, unless the tape wraps around, see `Program::lint`.
+    InfiniteLoop,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            LintKind::EmptyLoop => write!(f, "empty loop never ends once it is entered"),
+            LintKind::InfiniteLoop => write!(f, "loop never changes the cell it tests to zero, so it never ends once it is entered"),
+        }
+    }
+}
+
+/// What the body of a loop does to the cell the loop tests, as far as `Program::lint` follows it
+struct LoopBody {
+    head: usize,
+    /// Data pointer relative to the loop head
+    offset: isize,
+    /// Net amount added to the tested cell
+    delta: isize,
+    decremented: bool,
+    /// The body may set the tested cell to something unknown or moves the pointer by an unknown amount
+    unknown: bool,
+    empty: bool,
+}
+
+impl Program {
+    /// An upper bound for the rightmost cell the program can move the data pointer to or access, found without running it.
+    /// A tape of `max_pointer_offset() + 1` cells is enough for every input.
+    /// Returns `None` if the reach is unbounded, i.e. a loop can move the pointer further right with every iteration or the program scans to the right.
+    pub fn max_pointer_offset(&self) -> Option<usize> {
+        // Upper bound of the data pointer at the current instruction, relative to the start
+        let mut offset: isize = 0;
+        let mut max_offset: isize = 0;
+        let mut loop_head_offsets = Vec::new();
+
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::IncrementPointer(n) => offset += n as isize,
+                // Moving left never raises the bound, so a loop moving left with every iteration is still bounded
+                Instruction::DecrementPointer(n) => offset -= n as isize,
+                Instruction::FillZero { count } => offset += count as isize - 1,
+                Instruction::ScanRight(_) => return None,
+                Instruction::ScanLeft(_) => {}
+                Instruction::AddAtOffset { offset: target, .. } => max_offset = max_offset.max(offset + target),
+                Instruction::MultiplyAdd(ref targets) => {
+                    for &(target, _) in targets {
+                        max_offset = max_offset.max(offset + target);
+                    }
+                }
+                Instruction::CopyTo { ref targets } => {
+                    for &target in targets {
+                        max_offset = max_offset.max(offset + target);
+                    }
+                }
+                Instruction::LoopHead(_) => loop_head_offsets.push(offset),
+                Instruction::LoopEnd(_) => {
+                    let head_offset = loop_head_offsets.pop().unwrap();
+                    if offset > head_offset {
+                        return None;
+                    }
+                    // The loop may not run at all, leaving the pointer where it was
+                    offset = head_offset;
+                }
+                _ => {}
+            }
+            max_offset = max_offset.max(offset);
+        }
+
+        Some(max_offset as usize)
+    }
+
+    /// Estimates how many cells the program needs by assuming that every loop runs at most `loop_cap` times and every scan moves
+    /// at most `loop_cap` strides. Unlike `max_pointer_offset`, loops that move the pointer to the right get a finite bound.
+    /// This is a heuristic, not a proof: loops that run more often than `loop_cap` can reach further, so leave some room.
+    pub fn estimate_tape_usage(&self, loop_cap: u64) -> usize {
+        let cap = isize::try_from(loop_cap).unwrap_or(isize::MAX);
+
+        // Upper bound of the data pointer and the rightmost cell reached, relative to the start of the innermost loop body
+        let mut offset: isize = 0;
+        let mut max_offset: isize = 0;
+        // The offset and reach of the enclosing code when a loop was entered
+        let mut outer = Vec::new();
+
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::IncrementPointer(n) => offset = offset.saturating_add(n as isize),
+                Instruction::DecrementPointer(n) => offset = offset.saturating_sub(n as isize),
+                Instruction::FillZero { count } => offset = offset.saturating_add(count as isize - 1),
+                Instruction::ScanRight(n) => offset = offset.saturating_add(cap.saturating_mul(n as isize)),
+                Instruction::ScanLeft(_) => {}
+                Instruction::AddAtOffset { offset: target, .. } => max_offset = max_offset.max(offset.saturating_add(target)),
+                Instruction::MultiplyAdd(ref targets) => {
+                    for &(target, _) in targets {
+                        max_offset = max_offset.max(offset.saturating_add(target));
+                    }
+                }
+                Instruction::CopyTo { ref targets } => {
+                    for &target in targets {
+                        max_offset = max_offset.max(offset.saturating_add(target));
+                    }
+                }
+                Instruction::LoopHead(_) => {
+                    outer.push((offset, max_offset));
+                    offset = 0;
+                    max_offset = 0;
+                }
+                Instruction::LoopEnd(_) => {
+                    let (stride, body_reach) = (offset, max_offset);
+                    let (head_offset, outer_max_offset) = outer.pop().unwrap();
+
+                    // A loop moving right reaches furthest in its last iteration, any other loop in its first one
+                    let (reach, end_offset) = if stride > 0 && cap > 0 {
+                        let before_last = stride.saturating_mul(cap - 1);
+                        (before_last.saturating_add(body_reach), before_last.saturating_add(stride))
+                    } else if cap > 0 {
+                        (body_reach, 0)
+                    } else {
+                        (0, 0)
+                    };
+                    offset = head_offset.saturating_add(end_offset);
+                    max_offset = outer_max_offset.max(head_offset.saturating_add(reach));
+                }
+                _ => {}
+            }
+            max_offset = max_offset.max(offset);
+        }
+
+        (max_offset as usize).saturating_add(1)
+    }
+
+    /// Whether both programs lowered to the same instructions with the same arithmetic, regardless of comments, whitespace or how the
+    /// commands were split into runs (`++` and `+ +` are the same). Breakpoints, tape dumps and marks are ignored.
+    /// This only compares the structure, it does not prove that the programs behave the same. Programs that always behave the same but
+    /// change cells in a different order, like `>+<+` and `+>+<`, or run independent loops in a different order, are not equivalent.
+    pub fn structurally_equivalent(&self, other: &Program) -> bool {
+        let structure = |program: &Program| -> Vec<Instruction<Unbound>> {
+            program
+                .instructions
+                .iter()
+                .filter(|instruction| !matches!(instruction, Instruction::Breakpoint | Instruction::DumpTape | Instruction::Mark(_)))
+                // Loop addresses shift with the ignored instructions, the order of the brackets already determines the nesting
+                .map(|instruction| instruction.clone().map_address(|_| Unbound))
+                .collect()
+        };
+
+        self.arithmetic == other.arithmetic && structure(self) == structure(other)
+    }
+
+    /// Finds loops that obviously never end once they are entered, because they leave the data pointer where it was and never set
+    /// the cell they test to zero. This is a lightweight check that misses most infinite loops. What it reports is certain on a tape
+    /// that does not wrap around, but with `TapeMode::Wrap` or `ExecutionOptions::address_space` a cell at another offset can be the
+    /// tested one on a short tape, like in `+[>-<]` on a single cell, and the loop may end after all.
+    /// A loop at the very start of the program is skipped, as it never runs and is a common way to write comments. Unless the program
+    /// was parsed with `ParseOptions::fragment`, which continues on a tape whose current cell may be nonzero.
+    pub fn lint(&self) -> Vec<Lint> {
+        let fragment = self.parsed_from.as_ref().is_some_and(|(_, options)| options.fragment);
+        let mut lints = Vec::new();
+        let mut loop_bodies: Vec<LoopBody> = Vec::new();
+
+        for (address, instruction) in self.instructions.iter().enumerate() {
+            if let Instruction::LoopHead(_) = instruction {
+                // The inner loop may end at any pointer position and zeroes the cell it tests, which may be the one of the outer loop
+                if let Some(outer) = loop_bodies.last_mut() {
+                    outer.unknown = true;
+                }
+                loop_bodies.push(LoopBody { head: address, offset: 0, delta: 0, decremented: false, unknown: false, empty: true });
+                continue;
+            }
+
+            let body = match loop_bodies.last_mut() {
+                Some(body) => body,
+                None => continue,
+            };
+            if !matches!(instruction, Instruction::Breakpoint | Instruction::DumpTape | Instruction::Mark(_) | Instruction::LoopEnd(_)) {
+                body.empty = false;
+            }
+            let tested = body.offset == 0;
+            match *instruction {
+                Instruction::IncrementPointer(n) => body.offset += n as isize,
+                Instruction::DecrementPointer(n) => body.offset -= n as isize,
+                Instruction::IncrementValue(n) if tested => body.delta += n as isize,
+                Instruction::DecrementValue(n) if tested => {
+                    body.delta -= n as isize;
+                    body.decremented = true;
+                }
+                Instruction::AddAtOffset { offset, amount } if body.offset + offset == 0 => {
+                    body.delta += amount;
+                    body.decremented |= amount < 0;
+                }
+                Instruction::FillZero { count } => {
+                    body.unknown |= (body.offset..body.offset + count as isize).contains(&0);
+                    body.offset += count as isize - 1;
+                }
+                Instruction::MultiplyAdd(ref targets) => {
+                    body.unknown |= tested || targets.iter().any(|&(offset, _)| body.offset + offset == 0);
+                }
+                Instruction::CopyTo { ref targets } => {
+                    body.unknown |= tested || targets.iter().any(|&offset| body.offset + offset == 0);
+                }
+                Instruction::SetZero | Instruction::SetValue(_) | Instruction::GetChar(_) if tested => body.unknown = true,
+                Instruction::ScanRight(_) | Instruction::ScanLeft(_) => body.unknown = true,
+                Instruction::LoopEnd(_) => {
+                    let body = loop_bodies.pop().unwrap();
+                    // Saturating increments never reach zero, any other change may
+                    let unchanged = match self.arithmetic {
+                        CellArithmetic::Wrapping => body.delta == 0,
+                        CellArithmetic::Saturating => !body.decremented,
+                    };
+                    if (body.head != 0 || fragment) && !body.unknown && body.offset == 0 && unchanged {
+                        lints.push(Lint {
+                            kind: if body.empty { LintKind::EmptyLoop } else { LintKind::InfiniteLoop },
+                            source: self.source_map[body.head].start..self.source_map[address].end,
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        lints
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Lint, LintKind};
+    use crate::brainfuck::{ParseOptions, Program};
+
+    fn lint(code: &str, options: &ParseOptions) -> Vec<Lint> {
+        Program::parse_with_options(code, options).unwrap().lint()
+    }
+
+    #[test]
+    fn loops_that_never_change_their_cell_are_reported() {
+        let infinite = Lint { kind: LintKind::InfiniteLoop, source: 1..6 };
+        assert_eq!(lint("+[>+<]", &ParseOptions::default()), [infinite]);
+        assert_eq!(lint("+[]", &ParseOptions::default()), [Lint { kind: LintKind::EmptyLoop, source: 1..3 }]);
+        assert_eq!(lint("+[->+<]+[>+<-]", &ParseOptions::default()), []);
+    }
+
+    #[test]
+    fn leading_loops_are_only_reported_in_fragments() {
+        assert_eq!(lint("[>+<] is a comment", &ParseOptions::default()), []);
+        let fragment = ParseOptions {
+            fragment: true,
+            ..ParseOptions::default()
+        };
+        assert_eq!(lint("[>+<]", &fragment), [Lint { kind: LintKind::InfiniteLoop, source: 0..5 }]);
+    }
+}
@@ -0,0 +1,118 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+use super::CellArithmetic;
+
+/// A tape cell. Arithmetic wraps at the width of the implementing type, unless saturating arithmetic is chosen.
+/// `,` and `.` only transfer bytes: input is zero-extended and output is truncated to the low byte.
+pub trait Cell: Copy + Default + PartialEq + fmt::Debug {
+    /// Adds `n` modulo the cell size
+    fn wrapping_add(self, n: usize) -> Self;
+    /// Subtracts `n` modulo the cell size
+    fn wrapping_sub(self, n: usize) -> Self;
+    /// Adds `n`, stopping at the largest value
+    fn saturating_add(self, n: usize) -> Self;
+    /// Subtracts `n`, stopping at 0
+    fn saturating_sub(self, n: usize) -> Self;
+    fn is_zero(self) -> bool;
+    fn to_usize(self) -> usize;
+    /// The value read as a two's complement number of the cell's width, e.g. 200 in a `u8` is -56
+    fn to_isize(self) -> isize;
+    fn to_output_byte(self) -> u8;
+    fn from_input_byte(byte: u8) -> Self;
+}
+
+macro_rules! impl_cell {
+    ($($t:ty => $signed:ty),*) => {
+        $(
+            impl Cell for $t {
+                fn wrapping_add(self, n: usize) -> Self {
+                    // Truncating n keeps it congruent modulo the cell size
+                    <$t>::wrapping_add(self, n as $t)
+                }
+
+                fn wrapping_sub(self, n: usize) -> Self {
+                    <$t>::wrapping_sub(self, n as $t)
+                }
+
+                fn saturating_add(self, n: usize) -> Self {
+                    <$t>::saturating_add(self, <$t>::try_from(n).unwrap_or(<$t>::MAX))
+                }
+
+                fn saturating_sub(self, n: usize) -> Self {
+                    <$t>::saturating_sub(self, <$t>::try_from(n).unwrap_or(<$t>::MAX))
+                }
+
+                fn is_zero(self) -> bool {
+                    self == 0
+                }
+
+                fn to_usize(self) -> usize {
+                    self as usize
+                }
+
+                fn to_isize(self) -> isize {
+                    self as $signed as isize
+                }
+
+                fn to_output_byte(self) -> u8 {
+                    self as u8
+                }
+
+                fn from_input_byte(byte: u8) -> Self {
+                    byte as $t
+                }
+            }
+        )*
+    };
+}
+
+impl_cell!(u8 => i8, u16 => i16, u32 => i32);
+
+/// Adds `delta` to `cell` with the given arithmetic. Both directions wrap or saturate at the width of `C`, so this is the one place
+/// value changes like `+`, `-`, `AddAtOffset` and `MultiplyAdd` are applied.
+#[inline]
+pub(super) fn apply_delta<C: Cell>(cell: C, delta: isize, arithmetic: CellArithmetic) -> C {
+    match arithmetic {
+        // Negative deltas wrap around, as usize arithmetic is congruent modulo every cell size
+        CellArithmetic::Wrapping => cell.wrapping_add(delta as usize),
+        CellArithmetic::Saturating if delta < 0 => cell.saturating_sub(delta.unsigned_abs()),
+        CellArithmetic::Saturating => cell.saturating_add(delta as usize),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{apply_delta, CellArithmetic};
+
+    #[test]
+    fn wrapping_deltas_wrap_at_the_cell_size() {
+        let wrapping = CellArithmetic::Wrapping;
+        assert_eq!(apply_delta(255u8, 1, wrapping), 0);
+        assert_eq!(apply_delta(0u8, -1, wrapping), 255);
+        assert_eq!(apply_delta(7u8, 256, wrapping), 7);
+        assert_eq!(apply_delta(7u8, -257, wrapping), 6);
+        assert_eq!(apply_delta(0u8, isize::MIN, wrapping), 0);
+        assert_eq!(apply_delta(u16::MAX, 1, wrapping), 0);
+        assert_eq!(apply_delta(0u16, -1, wrapping), u16::MAX);
+        assert_eq!(apply_delta(1u16, 65_536, wrapping), 1);
+        assert_eq!(apply_delta(u32::MAX, 2, wrapping), 1);
+        assert_eq!(apply_delta(0u32, -1, wrapping), u32::MAX);
+    }
+
+    #[test]
+    fn saturating_deltas_stop_at_the_bounds() {
+        let saturating = CellArithmetic::Saturating;
+        assert_eq!(apply_delta(255u8, 1, saturating), 255);
+        assert_eq!(apply_delta(0u8, -1, saturating), 0);
+        assert_eq!(apply_delta(7u8, 256, saturating), 255);
+        assert_eq!(apply_delta(7u8, -257, saturating), 0);
+        assert_eq!(apply_delta(7u8, isize::MIN, saturating), 0);
+        assert_eq!(apply_delta(7u8, isize::MAX, saturating), 255);
+        assert_eq!(apply_delta(254u8, 1, saturating), 255);
+        assert_eq!(apply_delta(1u8, -1, saturating), 0);
+        assert_eq!(apply_delta(0u16, 300, saturating), 300);
+        assert_eq!(apply_delta(u16::MAX - 1, 2, saturating), u16::MAX);
+        assert_eq!(apply_delta(1u32, -2, saturating), 0);
+    }
+}
@@ -0,0 +1,685 @@
+use super::{CellArithmetic, Instruction, Program, DEFAULT_MEMORY_CAPACITY};
+
+impl Program {
+    /// Translates the program into a standalone C program with a tape of `DEFAULT_MEMORY_CAPACITY` bytes.
+    /// The generated code uses 8-bit cells.
+    pub fn to_c(&self) -> String {
+        self.to_c_with_capacity(DEFAULT_MEMORY_CAPACITY)
+    }
+
+    pub fn to_c_with_capacity(&self, memory_capacity: usize) -> String {
+        self.assert_wrapping();
+        let mut c = String::new();
+        c.push_str("#include <stdio.h>\n");
+        c.push_str("#include <string.h>\n\n");
+        c.push_str(&format!("static unsigned char mem[{}];\n\n", memory_capacity));
+        c.push_str("int main(void) {\n");
+        c.push_str("    unsigned char *ptr = mem;\n\n");
+
+        let mut depth = 1;
+        for instruction in &self.instructions {
+            if let Instruction::LoopEnd(_) = instruction {
+                depth -= 1;
+            }
+
+            let indentation = "    ".repeat(depth);
+            let statement = match *instruction {
+                Instruction::IncrementPointer(n) => format!("ptr += {};", n),
+                Instruction::DecrementPointer(n) => format!("ptr -= {};", n),
+                Instruction::IncrementValue(n) => format!("*ptr += {};", n % 256),
+                Instruction::DecrementValue(n) => format!("*ptr -= {};", n % 256),
+                Instruction::PutChar(1) => "putchar(*ptr);".to_string(),
+                Instruction::PutChar(n) => format!("for (int i = 0; i < {}; i++) putchar(*ptr);", n),
+                // EOF leaves the cell unchanged, like the interpreter does by default
+                Instruction::GetChar(1) => "{ int c; fflush(stdout); c = getchar(); if (c != EOF) *ptr = c; }".to_string(),
+                Instruction::GetChar(n) => format!(
+                    "for (int i = 0; i < {}; i++) {{ int c; fflush(stdout); c = getchar(); if (c != EOF) *ptr = c; }}",
+                    n
+                ),
+                Instruction::LoopHead(_) => "while (*ptr) {".to_string(),
+                Instruction::LoopEnd(_) => "}".to_string(),
+                Instruction::SetZero => "*ptr = 0;".to_string(),
+                Instruction::SetValue(value) => format!("*ptr = {};", value),
+                Instruction::FillZero { count } => format!("memset(ptr, 0, {}); ptr += {};", count, count - 1),
+                Instruction::MultiplyAdd(ref targets) => {
+                    let additions: String = targets
+                        .iter()
+                        .map(|(offset, factor)| format!(" ptr[{}] += *ptr * {};", offset, factor))
+                        .collect();
+                    format!("if (*ptr) {{{} *ptr = 0; }}", additions)
+                }
+                Instruction::CopyTo { ref targets } => {
+                    let additions: String = targets.iter().map(|offset| format!(" ptr[{}] += *ptr;", offset)).collect();
+                    format!("if (*ptr) {{{} *ptr = 0; }}", additions)
+                }
+                Instruction::ScanRight(n) => format!("while (*ptr) ptr += {};", n),
+                Instruction::ScanLeft(n) => format!("while (*ptr) ptr -= {};", n),
+                Instruction::AddAtOffset { offset, amount } => format!("ptr[{}] += {};", offset, amount),
+                Instruction::Breakpoint => "/* breakpoint */".to_string(),
+                Instruction::DumpTape => "/* dump tape */".to_string(),
+                Instruction::Mark(ref label) => format!("/* @{} */", label),
+            };
+            c.push_str(&indentation);
+            c.push_str(&statement);
+            c.push('\n');
+
+            if let Instruction::LoopHead(_) = instruction {
+                depth += 1;
+            }
+        }
+
+        c.push_str("\n    fflush(stdout);\n");
+        c.push_str("    return 0;\n");
+        c.push_str("}\n");
+        c
+    }
+}
+
+impl Program {
+    /// Translates the program into a standalone Rust program with a tape of `DEFAULT_MEMORY_CAPACITY` bytes.
+    /// The generated code uses 8-bit cells.
+    pub fn to_rust(&self) -> String {
+        self.to_rust_with_capacity(DEFAULT_MEMORY_CAPACITY)
+    }
+
+    pub fn to_rust_with_capacity(&self, memory_capacity: usize) -> String {
+        self.assert_wrapping();
+        let mut rust = String::new();
+        rust.push_str("#![allow(unused)]\n\n");
+        rust.push_str("use std::io::{self, Read, Write};\n\n");
+        rust.push_str("fn main() {\n");
+        rust.push_str(&format!("    let mut memory = vec![0u8; {}];\n", memory_capacity));
+        rust.push_str("    let mut ptr: usize = 0;\n");
+        rust.push_str("    let stdin = io::stdin();\n");
+        rust.push_str("    let mut input = io::BufReader::new(stdin.lock()).bytes();\n");
+        rust.push_str("    let stdout = io::stdout();\n");
+        rust.push_str("    let mut output = stdout.lock();\n\n");
+
+        let mut depth = 1;
+        for instruction in &self.instructions {
+            if let Instruction::LoopEnd(_) = instruction {
+                depth -= 1;
+            }
+
+            let indentation = "    ".repeat(depth);
+            let statement = match *instruction {
+                Instruction::IncrementPointer(n) => format!("ptr += {};", n),
+                Instruction::DecrementPointer(n) => format!("ptr -= {};", n),
+                Instruction::IncrementValue(n) => format!("memory[ptr] = memory[ptr].wrapping_add({});", n % 256),
+                Instruction::DecrementValue(n) => format!("memory[ptr] = memory[ptr].wrapping_sub({});", n % 256),
+                Instruction::PutChar(1) => "output.write_all(&[memory[ptr]]).unwrap();".to_string(),
+                Instruction::PutChar(n) => format!("for _ in 0..{} {{ output.write_all(&[memory[ptr]]).unwrap(); }}", n),
+                // EOF leaves the cell unchanged, like the interpreter does by default
+                Instruction::GetChar(1) => {
+                    "output.flush().unwrap(); if let Some(byte) = input.next() { memory[ptr] = byte.unwrap(); }".to_string()
+                }
+                Instruction::GetChar(n) => format!(
+                    "output.flush().unwrap(); for _ in 0..{} {{ if let Some(byte) = input.next() {{ memory[ptr] = byte.unwrap(); }} }}",
+                    n
+                ),
+                Instruction::LoopHead(_) => "while memory[ptr] != 0 {".to_string(),
+                Instruction::LoopEnd(_) => "}".to_string(),
+                Instruction::SetZero => "memory[ptr] = 0;".to_string(),
+                Instruction::SetValue(value) => format!("memory[ptr] = {};", value as u8),
+                Instruction::FillZero { count } => format!("memory[ptr..ptr + {}].fill(0); ptr += {};", count, count - 1),
+                Instruction::MultiplyAdd(ref targets) => {
+                    let additions: String = targets
+                        .iter()
+                        .map(|&(offset, factor)| {
+                            let target = Self::rust_index(offset);
+                            format!(
+                                " memory[{}] = memory[{}].wrapping_add(memory[ptr].wrapping_mul({}));",
+                                target, target, factor as u8
+                            )
+                        })
+                        .collect();
+                    format!("if memory[ptr] != 0 {{{} memory[ptr] = 0; }}", additions)
+                }
+                Instruction::CopyTo { ref targets } => {
+                    let additions: String = targets
+                        .iter()
+                        .map(|&offset| {
+                            let target = Self::rust_index(offset);
+                            format!(" memory[{}] = memory[{}].wrapping_add(memory[ptr]);", target, target)
+                        })
+                        .collect();
+                    format!("if memory[ptr] != 0 {{{} memory[ptr] = 0; }}", additions)
+                }
+                Instruction::ScanRight(n) => format!("while memory[ptr] != 0 {{ ptr += {}; }}", n),
+                Instruction::ScanLeft(n) => format!("while memory[ptr] != 0 {{ ptr -= {}; }}", n),
+                Instruction::AddAtOffset { offset, amount } => {
+                    let target = Self::rust_index(offset);
+                    format!("memory[{}] = memory[{}].wrapping_add({});", target, target, amount as u8)
+                }
+                Instruction::Breakpoint => "// breakpoint".to_string(),
+                Instruction::DumpTape => "// dump tape".to_string(),
+                Instruction::Mark(ref label) => format!("// @{}", label),
+            };
+            rust.push_str(&indentation);
+            rust.push_str(&statement);
+            rust.push('\n');
+
+            if let Instruction::LoopHead(_) = instruction {
+                depth += 1;
+            }
+        }
+
+        rust.push_str("\n    output.flush().unwrap();\n");
+        rust.push_str("}\n");
+        rust
+    }
+
+    fn assert_wrapping(&self) {
+        assert_eq!(
+            self.arithmetic,
+            CellArithmetic::Wrapping,
+            "Generated code only supports wrapping arithmetic"
+        );
+    }
+
+    fn rust_index(offset: isize) -> String {
+        if offset < 0 {
+            format!("ptr - {}", -offset)
+        } else {
+            format!("ptr + {}", offset)
+        }
+    }
+}
+
+impl Program {
+    /// Translates the program into textual LLVM IR with a tape of `DEFAULT_MEMORY_CAPACITY` bytes, to be compiled with `llc` or `clang`.
+    /// The generated code uses 8-bit cells and links against the C standard library for I/O.
+    pub fn to_llvm_ir(&self) -> String {
+        self.to_llvm_ir_with_capacity(DEFAULT_MEMORY_CAPACITY)
+    }
+
+    pub fn to_llvm_ir_with_capacity(&self, memory_capacity: usize) -> String {
+        self.assert_wrapping();
+        let mut ir = LlvmIr {
+            code: String::new(),
+            next_value: 0,
+        };
+        ir.code.push_str(&format!("@mem = internal global [{} x i8] zeroinitializer\n\n", memory_capacity));
+        ir.code.push_str("declare i32 @putchar(i32)\n");
+        ir.code.push_str("declare i32 @getchar()\n");
+        ir.code.push_str("declare i32 @fflush(ptr)\n");
+        ir.code.push_str("declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)\n\n");
+        ir.code.push_str("define i32 @main() {\n");
+        ir.code.push_str("entry:\n");
+        ir.code.push_str("  %ptr = alloca i64\n");
+        ir.code.push_str("  store i64 0, ptr %ptr\n");
+
+        // Number of every open loop, naming its body and exit blocks
+        let mut loops = Vec::new();
+        let mut next_loop = 0;
+
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::IncrementPointer(n) => ir.move_pointer(n as isize),
+                Instruction::DecrementPointer(n) => ir.move_pointer(-(n as isize)),
+                Instruction::IncrementValue(n) => ir.add_at_offset(0, n as u8),
+                Instruction::DecrementValue(n) => ir.add_at_offset(0, (n as u8).wrapping_neg()),
+                Instruction::SetZero => ir.set_current(0),
+                Instruction::SetValue(value) => ir.set_current(value as u8),
+                Instruction::FillZero { count } => {
+                    let address = ir.address(0);
+                    ir.code.push_str(&format!("  call void @llvm.memset.p0.i64(ptr %v{}, i8 0, i64 {}, i1 false)\n", address, count));
+                    ir.move_pointer(count as isize - 1);
+                }
+                Instruction::AddAtOffset { offset, amount } => ir.add_at_offset(offset, amount as u8),
+                Instruction::MultiplyAdd(ref targets) => {
+                    let label = ir.value();
+                    let value = ir.load_current();
+                    ir.branch_if_nonzero(value, &format!("multiply{}", label), &format!("multiply{}.end", label));
+
+                    ir.code.push_str(&format!("multiply{}:\n", label));
+                    for &(offset, factor) in targets {
+                        let address = ir.address(offset);
+                        let cell = ir.value();
+                        let product = ir.value();
+                        let sum = ir.value();
+                        ir.code.push_str(&format!("  %v{} = load i8, ptr %v{}\n", cell, address));
+                        ir.code.push_str(&format!("  %v{} = mul i8 %v{}, {}\n", product, value, factor as u8 as i8));
+                        ir.code.push_str(&format!("  %v{} = add i8 %v{}, %v{}\n", sum, cell, product));
+                        ir.code.push_str(&format!("  store i8 %v{}, ptr %v{}\n", sum, address));
+                    }
+                    ir.set_current(0);
+                    ir.code.push_str(&format!("  br label %multiply{}.end\n", label));
+                    ir.code.push_str(&format!("multiply{}.end:\n", label));
+                }
+                Instruction::CopyTo { ref targets } => {
+                    let label = ir.value();
+                    let value = ir.load_current();
+                    ir.branch_if_nonzero(value, &format!("copy{}", label), &format!("copy{}.end", label));
+
+                    ir.code.push_str(&format!("copy{}:\n", label));
+                    for &offset in targets {
+                        let address = ir.address(offset);
+                        let cell = ir.value();
+                        let sum = ir.value();
+                        ir.code.push_str(&format!("  %v{} = load i8, ptr %v{}\n", cell, address));
+                        ir.code.push_str(&format!("  %v{} = add i8 %v{}, %v{}\n", sum, cell, value));
+                        ir.code.push_str(&format!("  store i8 %v{}, ptr %v{}\n", sum, address));
+                    }
+                    ir.set_current(0);
+                    ir.code.push_str(&format!("  br label %copy{}.end\n", label));
+                    ir.code.push_str(&format!("copy{}.end:\n", label));
+                }
+                Instruction::ScanRight(n) => ir.scan(n as isize),
+                Instruction::ScanLeft(n) => ir.scan(-(n as isize)),
+                Instruction::LoopHead(_) => {
+                    let value = ir.load_current();
+                    ir.branch_if_nonzero(value, &format!("loop{}", next_loop), &format!("loop{}.end", next_loop));
+                    ir.code.push_str(&format!("loop{}:\n", next_loop));
+                    loops.push(next_loop);
+                    next_loop += 1;
+                }
+                Instruction::LoopEnd(_) => {
+                    let label = loops.pop().unwrap();
+                    let value = ir.load_current();
+                    ir.branch_if_nonzero(value, &format!("loop{}", label), &format!("loop{}.end", label));
+                    ir.code.push_str(&format!("loop{}.end:\n", label));
+                }
+                Instruction::PutChar(1) => {
+                    let value = ir.load_current();
+                    ir.put_char(value);
+                }
+                Instruction::PutChar(n) => {
+                    let value = ir.load_current();
+                    let repeat = ir.begin_repeat();
+                    ir.put_char(value);
+                    ir.end_repeat(repeat, n);
+                }
+                // EOF leaves the cell unchanged, like the interpreter does by default
+                Instruction::GetChar(1) => ir.get_char(),
+                Instruction::GetChar(n) => {
+                    let repeat = ir.begin_repeat();
+                    ir.get_char();
+                    ir.end_repeat(repeat, n);
+                }
+                Instruction::Breakpoint => ir.code.push_str("  ; breakpoint\n"),
+                Instruction::DumpTape => ir.code.push_str("  ; dump tape\n"),
+                Instruction::Mark(ref label) => ir.code.push_str(&format!("  ; @{}\n", label)),
+            }
+        }
+
+        let flushed = ir.value();
+        ir.code.push_str(&format!("  %v{} = call i32 @fflush(ptr null)\n", flushed));
+        ir.code.push_str("  ret i32 0\n");
+        ir.code.push_str("}\n");
+        ir.code
+    }
+}
+
+impl Program {
+    /// Renders the optimized program back to brainfuck without comments or whitespace.
+    /// Lowered loops are written as the loop they stand for, like `[-]` for `SetZero`, so parsing the result with the same
+    /// `ParseOptions` yields a program with the same output and final tape. The instructions are the same too, unless a loop
+    /// that is never entered was removed: its neighbours are adjacent in the result, and the optimizer may combine them, like
+    /// `,[-][.]+++` that becomes `GetChar(1), SetZero, IncrementValue(3)` but `,[-]+++` and `SetValue(3)` when parsed again.
+    /// `#` and `@label` are kept and need the matching options to be parsed again.
+    pub fn to_canonical_source(&self) -> String {
+        let mut source = CanonicalSource {
+            code: String::new(),
+            pending_move: 0,
+        };
+        for instruction in &self.instructions {
+            match *instruction {
+                Instruction::IncrementPointer(n) => source.move_pointer(n as isize),
+                Instruction::DecrementPointer(n) => source.move_pointer(-(n as isize)),
+                Instruction::IncrementValue(n) => source.change(n as isize),
+                Instruction::DecrementValue(n) => source.change(-(n as isize)),
+                Instruction::PutChar(n) => source.push(&".".repeat(n)),
+                Instruction::GetChar(n) => source.push(&",".repeat(n)),
+                Instruction::LoopHead(_) => source.push("["),
+                Instruction::LoopEnd(_) => source.push("]"),
+                Instruction::SetZero => source.push("[-]"),
+                Instruction::FillZero { count } => {
+                    source.push("[-]");
+                    for _ in 1..count {
+                        source.move_pointer(1);
+                        source.push("[-]");
+                    }
+                }
+                Instruction::SetValue(value) => {
+                    source.push("[-]");
+                    source.change(value);
+                }
+                Instruction::MultiplyAdd(ref targets) => {
+                    source.push("[-");
+                    for &(offset, factor) in targets {
+                        source.move_pointer(offset);
+                        source.change(factor);
+                        source.move_pointer(-offset);
+                    }
+                    source.push("]");
+                }
+                Instruction::CopyTo { ref targets } => {
+                    source.push("[-");
+                    for &offset in targets {
+                        source.move_pointer(offset);
+                        source.change(1);
+                        source.move_pointer(-offset);
+                    }
+                    source.push("]");
+                }
+                Instruction::ScanRight(n) => source.push(&format!("[{}]", ">".repeat(n))),
+                Instruction::ScanLeft(n) => source.push(&format!("[{}]", "<".repeat(n))),
+                Instruction::AddAtOffset { offset, amount } => {
+                    source.move_pointer(offset);
+                    source.change(amount);
+                    source.move_pointer(-offset);
+                }
+                Instruction::Breakpoint => source.push("#"),
+                Instruction::DumpTape => source.push("?"),
+                Instruction::Mark(ref label) => source.push(&format!("@{}", label)),
+            }
+        }
+        source.push("");
+        source.code
+    }
+}
+
+impl Program {
+    /// Renders the control flow graph in Graphviz DOT, e.g. for `dot -Tpng`.
+    /// Every run of instructions without brackets is a node labeled with its range of instruction indices, every bracket
+    /// is a node of its own with an edge for a zero and a nonzero cell. The program ends in the `exit` node.
+    pub fn to_dot(&self) -> String {
+        let length = self.instructions.len();
+        let is_bracket = |instruction: &Instruction| matches!(instruction, Instruction::LoopHead(_) | Instruction::LoopEnd(_));
+
+        // Blocks start at the beginning, at every bracket and right after it. The last one ends with the program
+        let mut starts = vec![false; length + 1];
+        starts[0] = true;
+        starts[length] = true;
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            if is_bracket(instruction) {
+                starts[index] = true;
+                starts[index + 1] = true;
+            }
+        }
+        let node = |index: usize| {
+            if index == length {
+                "exit".to_string()
+            } else {
+                format!("b{}", index)
+            }
+        };
+
+        let mut dot = String::from("digraph program {\n  node [shape=box];\n");
+        let mut start = 0;
+        while start < length {
+            let end = (start + 1..=length).find(|&index| starts[index]).unwrap();
+            match self.instructions[start] {
+                Instruction::LoopHead(loop_end) => {
+                    dot.push_str(&format!("  b{} [label=\"{}: [\", shape=diamond];\n", start, start));
+                    dot.push_str(&format!("  b{} -> {} [label=\"nonzero\"];\n", start, node(start + 1)));
+                    dot.push_str(&format!("  b{} -> {} [label=\"zero\"];\n", start, node(loop_end + 1)));
+                }
+                Instruction::LoopEnd(loop_head) => {
+                    dot.push_str(&format!("  b{} [label=\"{}: ]\", shape=diamond];\n", start, start));
+                    dot.push_str(&format!("  b{} -> {} [label=\"nonzero\"];\n", start, node(loop_head + 1)));
+                    dot.push_str(&format!("  b{} -> {} [label=\"zero\"];\n", start, node(start + 1)));
+                }
+                _ => {
+                    dot.push_str(&format!("  b{} [label=\"{}..{}\"];\n", start, start, end));
+                    dot.push_str(&format!("  b{} -> {};\n", start, node(end)));
+                }
+            }
+            start = end;
+        }
+        dot.push_str("  exit [shape=oval];\n");
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+/// The source being built by `Program::to_canonical_source`.
+/// Pointer moves are only written once something else follows, so moving back and forth between offsets collapses into the net move.
+struct CanonicalSource {
+    code: String,
+    pending_move: isize,
+}
+
+impl CanonicalSource {
+    fn move_pointer(&mut self, offset: isize) {
+        self.pending_move += offset;
+    }
+
+    fn change(&mut self, amount: isize) {
+        let command = if amount < 0 { "-" } else { "+" };
+        self.push(&command.repeat(amount.unsigned_abs()));
+    }
+
+    fn push(&mut self, commands: &str) {
+        let command = if self.pending_move < 0 { "<" } else { ">" };
+        self.code.push_str(&command.repeat(self.pending_move.unsigned_abs()));
+        self.pending_move = 0;
+        self.code.push_str(commands);
+    }
+}
+
+/// Values of a loop opened by `LlvmIr::begin_repeat`
+struct Repeat {
+    label: usize,
+    counter: usize,
+    next: usize,
+}
+
+/// The function body being built by `Program::to_llvm_ir`. Values are numbered `%v0`, `%v1`, ...
+struct LlvmIr {
+    code: String,
+    next_value: usize,
+}
+
+impl LlvmIr {
+    fn value(&mut self) -> usize {
+        self.next_value += 1;
+        self.next_value - 1
+    }
+
+    /// Computes the address of the cell `offset` cells away from the data pointer
+    fn address(&mut self, offset: isize) -> usize {
+        let pointer = self.value();
+        let index = self.value();
+        let address = self.value();
+        self.code.push_str(&format!("  %v{} = load i64, ptr %ptr\n", pointer));
+        self.code.push_str(&format!("  %v{} = add i64 %v{}, {}\n", index, pointer, offset));
+        self.code.push_str(&format!("  %v{} = getelementptr inbounds i8, ptr @mem, i64 %v{}\n", address, index));
+        address
+    }
+
+    fn load_current(&mut self) -> usize {
+        let address = self.address(0);
+        let value = self.value();
+        self.code.push_str(&format!("  %v{} = load i8, ptr %v{}\n", value, address));
+        value
+    }
+
+    fn set_current(&mut self, value: u8) {
+        let address = self.address(0);
+        self.code.push_str(&format!("  store i8 {}, ptr %v{}\n", value as i8, address));
+    }
+
+    fn add_at_offset(&mut self, offset: isize, amount: u8) {
+        let address = self.address(offset);
+        let value = self.value();
+        let sum = self.value();
+        self.code.push_str(&format!("  %v{} = load i8, ptr %v{}\n", value, address));
+        self.code.push_str(&format!("  %v{} = add i8 %v{}, {}\n", sum, value, amount as i8));
+        self.code.push_str(&format!("  store i8 %v{}, ptr %v{}\n", sum, address));
+    }
+
+    /// Starts a loop running the following code a fixed number of times, see `end_repeat`
+    fn begin_repeat(&mut self) -> Repeat {
+        let label = self.value();
+        let counter = self.value();
+        let next = self.value();
+        // The counter needs a named block to come from
+        self.code.push_str(&format!("  br label %repeat{}.start\n", label));
+        self.code.push_str(&format!("repeat{}.start:\n", label));
+        self.code.push_str(&format!("  br label %repeat{}\n", label));
+        self.code.push_str(&format!("repeat{}:\n", label));
+        self.code.push_str(&format!(
+            "  %v{} = phi i64 [ 0, %repeat{}.start ], [ %v{}, %repeat{}.next ]\n",
+            counter, label, next, label
+        ));
+        Repeat { label, counter, next }
+    }
+
+    fn end_repeat(&mut self, repeat: Repeat, count: usize) {
+        let Repeat { label, counter, next } = repeat;
+        let condition = self.value();
+        self.code.push_str(&format!("  br label %repeat{}.next\n", label));
+        self.code.push_str(&format!("repeat{}.next:\n", label));
+        self.code.push_str(&format!("  %v{} = add i64 %v{}, 1\n", next, counter));
+        self.code.push_str(&format!("  %v{} = icmp ult i64 %v{}, {}\n", condition, next, count));
+        self.code.push_str(&format!(
+            "  br i1 %v{}, label %repeat{}, label %repeat{}.end\n",
+            condition, label, label
+        ));
+        self.code.push_str(&format!("repeat{}.end:\n", label));
+    }
+
+    fn get_char(&mut self) {
+        let flushed = self.value();
+        let byte = self.value();
+        let is_eof = self.value();
+        let truncated = self.value();
+        self.code.push_str(&format!("  %v{} = call i32 @fflush(ptr null)\n", flushed));
+        self.code.push_str(&format!("  %v{} = call i32 @getchar()\n", byte));
+        self.code.push_str(&format!("  %v{} = icmp eq i32 %v{}, -1\n", is_eof, byte));
+        self.code.push_str(&format!("  br i1 %v{}, label %read{}.end, label %read{}\n", is_eof, byte, byte));
+        self.code.push_str(&format!("read{}:\n", byte));
+        self.code.push_str(&format!("  %v{} = trunc i32 %v{} to i8\n", truncated, byte));
+        let address = self.address(0);
+        self.code.push_str(&format!("  store i8 %v{}, ptr %v{}\n", truncated, address));
+        self.code.push_str(&format!("  br label %read{}.end\n", byte));
+        self.code.push_str(&format!("read{}.end:\n", byte));
+    }
+
+    fn put_char(&mut self, value: usize) {
+        let extended = self.value();
+        let result = self.value();
+        self.code.push_str(&format!("  %v{} = zext i8 %v{} to i32\n", extended, value));
+        self.code.push_str(&format!("  %v{} = call i32 @putchar(i32 %v{})\n", result, extended));
+    }
+
+    fn move_pointer(&mut self, offset: isize) {
+        let pointer = self.value();
+        let moved = self.value();
+        self.code.push_str(&format!("  %v{} = load i64, ptr %ptr\n", pointer));
+        self.code.push_str(&format!("  %v{} = add i64 %v{}, {}\n", moved, pointer, offset));
+        self.code.push_str(&format!("  store i64 %v{}, ptr %ptr\n", moved));
+    }
+
+    fn branch_if_nonzero(&mut self, value: usize, nonzero: &str, zero: &str) {
+        let condition = self.value();
+        self.code.push_str(&format!("  %v{} = icmp ne i8 %v{}, 0\n", condition, value));
+        self.code.push_str(&format!("  br i1 %v{}, label %{}, label %{}\n", condition, nonzero, zero));
+    }
+
+    fn scan(&mut self, step: isize) {
+        let label = self.value();
+        self.code.push_str(&format!("  br label %scan{}\n", label));
+        self.code.push_str(&format!("scan{}:\n", label));
+        let value = self.load_current();
+        self.branch_if_nonzero(value, &format!("scan{}.step", label), &format!("scan{}.end", label));
+        self.code.push_str(&format!("scan{}.step:\n", label));
+        self.move_pointer(step);
+        self.code.push_str(&format!("  br label %scan{}\n", label));
+        self.code.push_str(&format!("scan{}.end:\n", label));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::brainfuck::{CellArithmetic, Instruction, Program};
+
+    /// A program with every instruction, whose generated code is compared with the files in `src/test`
+    fn every_instruction() -> Program {
+        let instructions = vec![
+            Instruction::IncrementPointer(2),
+            Instruction::DecrementPointer(1),
+            Instruction::IncrementValue(3),
+            Instruction::DecrementValue(1),
+            Instruction::PutChar(1),
+            Instruction::PutChar(2),
+            Instruction::GetChar(1),
+            Instruction::GetChar(2),
+            Instruction::LoopHead(0),
+            Instruction::SetZero,
+            Instruction::SetValue(-2),
+            Instruction::FillZero { count: 3 },
+            Instruction::MultiplyAdd(vec![(1, 2), (-1, -3)]),
+            Instruction::CopyTo { targets: vec![-2, 1] },
+            Instruction::ScanRight(2),
+            Instruction::ScanLeft(1),
+            Instruction::AddAtOffset { offset: -1, amount: 5 },
+            Instruction::Breakpoint,
+            Instruction::DumpTape,
+            Instruction::Mark("end".to_string()),
+            Instruction::LoopEnd(0),
+        ];
+        Program::from_instructions(instructions, CellArithmetic::Wrapping).unwrap()
+    }
+
+    #[test]
+    fn c_of_every_instruction() {
+        assert_eq!(every_instruction().to_c_with_capacity(16), include_str!("../test/every_instruction.c"));
+    }
+
+    #[test]
+    fn rust_of_every_instruction() {
+        assert_eq!(every_instruction().to_rust_with_capacity(16), include_str!("../test/every_instruction.rs"));
+    }
+
+    #[test]
+    fn llvm_ir_of_every_instruction() {
+        assert_eq!(every_instruction().to_llvm_ir_with_capacity(16), include_str!("../test/every_instruction.ll"));
+    }
+
+    #[test]
+    fn dot_of_every_instruction() {
+        assert_eq!(every_instruction().to_dot(), include_str!("../test/every_instruction.dot"));
+    }
+
+    #[test]
+    fn canonical_source_parses_to_the_same_instructions() {
+        let programs = [
+            "A comment then code: ,>+++<[->++>+<<]>>[-]>[-]>[-]<<<[>]+[<<]-.[-]+++.",
+            "+++++[>+++++<-]>[>++>+<<-]>>[<]<<,[->+<]>.",
+            // An unrolled loop whose copies meet, a multiply and a copy loop, and scans with a stride
+            ",[-]++[-.-.+]>,[-]+++[>>[-]<<->[-]<]",
+            ",[->+++>-<<]>[->+>+<<]>.>.",
+            ",[>>>]<[<<]+[<]>.",
+            include_str!("../test/mandelbrot.bf"),
+        ];
+        for code in programs {
+            let program = Program::parse(code).unwrap();
+            let canonical = program.to_canonical_source();
+            assert!(canonical.chars().all(|c| "+-<>[].,".contains(c)), "{:?}", canonical);
+            assert_eq!(Program::parse(&canonical).unwrap().instructions(), program.instructions(), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn canonical_source_of_removed_loops_behaves_the_same() {
+        let run = |program: &Program| {
+            let mut memory = [0u8; 4];
+            let output = program.run_to_string(&mut memory, b"a").unwrap();
+            (output, memory)
+        };
+        for code in [",[-][.]+++.", ",[-][.]>[-]>+.", "[.]+>+<+.>."] {
+            let program = Program::parse(code).unwrap();
+            let again = Program::parse(&program.to_canonical_source()).unwrap();
+            assert_ne!(again.instructions(), program.instructions(), "{:?}", code);
+            assert_eq!(run(&again), run(&program), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn canonical_source_writes_lowered_loops_as_loops() {
+        assert_eq!(Program::parse(",[-]>,[-]+++ comment").unwrap().to_canonical_source(), ",[-]>,[-]+++");
+    }
+}
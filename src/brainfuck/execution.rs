@@ -0,0 +1,140 @@
+use std::io::{self, Read, StdinLock, StdoutLock, Write};
+use std::num::NonZeroUsize;
+use std::time::Duration;
+
+use super::{EofBehavior, ExecutionOptions, ExecutionStats, Halt, Interrupt, OutputEncoding, OutputMask, Signedness, TapeMode};
+
+/// Collects everything `Program::execute` needs for a run.
+/// `Execution::new()` matches `Program::run_growable`: a growable tape, stdin/stdout and the default `ExecutionOptions`.
+pub struct Execution<R: Read = StdinLock<'static>, W: Write = StdoutLock<'static>> {
+    pub(super) options: ExecutionOptions,
+    pub(super) memory_capacity: Option<usize>,
+    pub(super) collect_stats: bool,
+    pub(super) input: R,
+    pub(super) output: W,
+}
+
+impl Execution {
+    pub fn new() -> Self {
+        Execution {
+            options: ExecutionOptions::default(),
+            memory_capacity: None,
+            collect_stats: false,
+            input: io::stdin().lock(),
+            output: io::stdout().lock(),
+        }
+    }
+}
+
+impl Default for Execution {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: Read, W: Write> Execution<R, W> {
+    /// Replaces all options set so far
+    pub fn options(mut self, options: ExecutionOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn eof_behavior(mut self, eof_behavior: EofBehavior) -> Self {
+        self.options.eof_behavior = eof_behavior;
+        self
+    }
+
+    pub fn output_encoding(mut self, output_encoding: OutputEncoding) -> Self {
+        self.options.output_encoding = output_encoding;
+        self
+    }
+
+    pub fn output_mask(mut self, output_mask: OutputMask) -> Self {
+        self.options.output_mask = output_mask;
+        self
+    }
+
+    pub fn signedness(mut self, signedness: Signedness) -> Self {
+        self.options.signedness = signedness;
+        self
+    }
+
+    pub fn tape_mode(mut self, tape_mode: TapeMode) -> Self {
+        self.options.tape_mode = tape_mode;
+        self
+    }
+
+    pub fn input_length(mut self, input_length: u64) -> Self {
+        self.options.input_length = Some(input_length);
+        self
+    }
+
+    pub fn address_space(mut self, address_space: NonZeroUsize) -> Self {
+        self.options.address_space = Some(address_space);
+        self
+    }
+
+    pub fn max_steps(mut self, max_steps: u64) -> Self {
+        self.options.max_steps = Some(max_steps);
+        self
+    }
+
+    pub fn max_output(mut self, max_output: u64) -> Self {
+        self.options.max_output = Some(max_output);
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn interrupt(mut self, interrupt: Interrupt) -> Self {
+        self.options.interrupt = Some(interrupt);
+        self
+    }
+
+    /// Runs on a tape of exactly `capacity` cells instead of a growable one
+    pub fn memory_capacity(mut self, capacity: usize) -> Self {
+        self.memory_capacity = Some(capacity);
+        self
+    }
+
+    /// Fills `ExecutionReport::stats`. Off by default because counting slows down the execution.
+    pub fn collect_stats(mut self, collect_stats: bool) -> Self {
+        self.collect_stats = collect_stats;
+        self
+    }
+
+    pub fn input<I: Read>(self, input: I) -> Execution<I, W> {
+        Execution {
+            options: self.options,
+            memory_capacity: self.memory_capacity,
+            collect_stats: self.collect_stats,
+            input,
+            output: self.output,
+        }
+    }
+
+    pub fn output<O: Write>(self, output: O) -> Execution<R, O> {
+        Execution {
+            options: self.options,
+            memory_capacity: self.memory_capacity,
+            collect_stats: self.collect_stats,
+            input: self.input,
+            output,
+        }
+    }
+}
+
+/// The outcome of `Program::execute`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct ExecutionReport<C> {
+    pub halt: Halt,
+    /// The final tape
+    pub memory: Vec<C>,
+    /// The index in `memory` of the cell the program started on, non-zero if the tape grew to the left in `TapeMode::Bidirectional`
+    pub origin: usize,
+    /// Only collected if requested with `Execution::collect_stats`
+    pub stats: Option<ExecutionStats>,
+}
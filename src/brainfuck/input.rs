@@ -0,0 +1,133 @@
+use std::io::{self, BufRead, Read};
+
+/// Wraps the input of a program to translate `\r\n` line endings to `\n`, so programs expecting Unix line endings work on Windows input.
+/// A `\r` that is not followed by `\n` is kept unless `strip_lone_cr` is set.
+pub struct NormalizeLineEndings<R> {
+    inner: io::BufReader<R>,
+    strip_lone_cr: bool,
+    /// A `\r` was read, but not what follows it
+    pending_cr: bool,
+}
+
+impl<R: Read> NormalizeLineEndings<R> {
+    pub fn new(inner: R) -> NormalizeLineEndings<R> {
+        NormalizeLineEndings {
+            inner: io::BufReader::new(inner),
+            strip_lone_cr: false,
+            pending_cr: false,
+        }
+    }
+
+    /// Also removes every `\r` that is not part of a `\r\n`
+    pub fn strip_lone_cr(mut self, strip_lone_cr: bool) -> Self {
+        self.strip_lone_cr = strip_lone_cr;
+        self
+    }
+}
+
+impl<R: Read> Read for NormalizeLineEndings<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            // Only block for more input if nothing was read yet
+            let available = if written == 0 {
+                self.inner.fill_buf()?
+            } else {
+                self.inner.buffer()
+            };
+
+            let byte = match available.first() {
+                Some(&byte) => byte,
+                // A `\r` at the end of the input is not followed by anything
+                None if written == 0 && self.pending_cr => {
+                    self.pending_cr = false;
+                    if !self.strip_lone_cr {
+                        buf[0] = b'\r';
+                        written = 1;
+                    }
+                    break;
+                }
+                None => break,
+            };
+
+            if self.pending_cr {
+                self.pending_cr = false;
+                if byte != b'\n' && !self.strip_lone_cr {
+                    // Write the lone `\r`, the byte after it is looked at again in the next iteration
+                    buf[written] = b'\r';
+                    written += 1;
+                    continue;
+                }
+            }
+
+            self.inner.consume(1);
+            if byte == b'\r' {
+                self.pending_cr = true;
+            } else {
+                buf[written] = byte;
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use super::NormalizeLineEndings;
+
+    /// Returns one of `chunks` per read, like a pipe or terminal that delivers the input piecewise
+    struct Chunks(Vec<&'static [u8]>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            let chunk = self.0.remove(0);
+            buf[..chunk.len()].copy_from_slice(chunk);
+            Ok(chunk.len())
+        }
+    }
+
+    /// Reads everything with reads of at most `read_size` bytes
+    fn normalize(chunks: Vec<&'static [u8]>, strip_lone_cr: bool, read_size: usize) -> Vec<u8> {
+        let mut reader = NormalizeLineEndings::new(Chunks(chunks)).strip_lone_cr(strip_lone_cr);
+        let mut normalized = Vec::new();
+        let mut buf = vec![0; read_size];
+        loop {
+            match reader.read(&mut buf).unwrap() {
+                0 => return normalized,
+                read => normalized.extend_from_slice(&buf[..read]),
+            }
+        }
+    }
+
+    #[test]
+    fn crlf_becomes_lf() {
+        for read_size in [1, 2, 64] {
+            assert_eq!(normalize(vec![b"a\r\nb\r\n\r\n"], false, read_size), b"a\nb\n\n", "{}", read_size);
+            assert_eq!(normalize(vec![b"a\nb"], false, read_size), b"a\nb", "{}", read_size);
+        }
+    }
+
+    #[test]
+    fn lone_cr_is_kept_unless_stripped() {
+        for read_size in [1, 2, 64] {
+            assert_eq!(normalize(vec![b"a\rb\r\r\n\r"], false, read_size), b"a\rb\r\n\r", "{}", read_size);
+            assert_eq!(normalize(vec![b"a\rb\r\r\n\r"], true, read_size), b"ab\n", "{}", read_size);
+        }
+    }
+
+    #[test]
+    fn cr_may_be_split_from_its_lf_across_reads() {
+        for read_size in [1, 2, 64] {
+            assert_eq!(normalize(vec![b"a\r", b"\nb"], false, read_size), b"a\nb", "{}", read_size);
+            assert_eq!(normalize(vec![b"a\r", b"b"], false, read_size), b"a\rb", "{}", read_size);
+            assert_eq!(normalize(vec![b"a\r", b"b"], true, read_size), b"ab", "{}", read_size);
+            assert_eq!(normalize(vec![b"\r", b"\r", b"\n"], false, read_size), b"\r\n", "{}", read_size);
+        }
+    }
+}
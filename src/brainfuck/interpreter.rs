@@ -0,0 +1,747 @@
+use std::collections::VecDeque;
+use std::convert::TryFrom;
+use std::time::Instant;
+
+use super::cell::apply_delta;
+use super::tape::Tape;
+use super::{Cell, CellArithmetic, EofBehavior, ExecutionOptions, Halt, Instruction, Program, RuntimeError, TapeMode};
+
+/// Number of steps between two checks of `ExecutionOptions::timeout`.
+/// Reading the clock costs about as much as executing a few dozen instructions, checking every 65536 steps makes that
+/// negligible while still stopping within a millisecond of the timeout.
+pub const TIMEOUT_CHECK_INTERVAL: u64 = 1 << 16;
+
+/// What happened during a single `Interpreter::step`.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum StepResult {
+    /// An instruction was executed
+    Continue,
+    /// A `#` was executed
+    Breakpoint,
+    /// A `?` was executed, the cells around the data pointer should be shown to the user.
+    /// `Program::run_io` prints them to stderr, `Program::run_with_callbacks` and `Program::bench_run` ignore it.
+    DumpTape,
+    /// `.` was executed, the byte has to be written to the output.
+    /// A `PutChar(n)` produces this `n` times before the instruction pointer moves on.
+    // Carrying the count here instead makes the result too large to be returned in registers, which doubles the run time
+    Output(u8),
+    /// `,` is waiting for input, see `Interpreter::provide_input` and `Interpreter::provide_eof`.
+    /// The instruction pointer is not advanced, the next `step` retries the read.
+    NeedsInput,
+    /// Execution stopped, further steps do nothing
+    Halted(Halt),
+}
+
+/// Why `Interpreter::run_until_input` gave control back to the caller.
+#[derive(PartialEq, Eq, Clone, Copy, Debug)]
+pub enum Yield {
+    /// `.` was executed, the byte has to be written to the output. See `StepResult::Output`.
+    Output(u8),
+    /// `,` is waiting for input. Call `Interpreter::provide_input` or `Interpreter::provide_eof` before resuming.
+    NeedsInput,
+    /// Execution stopped, resuming returns the same `Halt` again
+    Halted(Halt),
+}
+
+/// The complete state of an `Interpreter`, see `Interpreter::snapshot`.
+#[derive(PartialEq, Eq, Clone, Debug)]
+pub struct Snapshot<C: Cell = u8> {
+    instruction_pointer: usize,
+    data_pointer: usize,
+    memory: Vec<C>,
+    input: VecDeque<u8>,
+    input_closed: bool,
+    bytes_read: u64,
+    steps: u64,
+    repetitions: usize,
+    origin: usize,
+}
+
+impl<C: Cell> Snapshot<C> {
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    pub fn memory(&self) -> &[C] {
+        &self.memory
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+}
+
+/// Executes a `Program` one instruction at a time on a borrowed or a growable tape.
+/// `Program::run_io` is a loop over `step` that connects the results to a reader and a writer.
+pub struct Interpreter<'a, C: Cell = u8> {
+    instructions: &'a [Instruction],
+    arithmetic: CellArithmetic,
+    memory: Tape<'a, C>,
+    options: ExecutionOptions,
+    instruction_pointer: usize,
+    data_pointer: usize,
+    input: VecDeque<u8>,
+    input_closed: bool,
+    /// Number of reads counted against `ExecutionOptions::input_length`, including the padding
+    bytes_read: u64,
+    steps: u64,
+    /// How often the current `PutChar` already wrote its byte
+    repetitions: usize,
+    /// Number of cells `TapeMode::Bidirectional` added before the initial first cell
+    origin: usize,
+    /// When `ExecutionOptions::timeout` passes, `None` if there is no timeout
+    deadline: Option<Instant>,
+}
+
+impl<'a, C: Cell> Interpreter<'a, C> {
+    pub fn new(program: &'a Program, memory: &'a mut [C]) -> Interpreter<'a, C> {
+        Self::with_options(program, memory, ExecutionOptions::default())
+    }
+
+    pub fn with_options(program: &'a Program, memory: &'a mut [C], options: ExecutionOptions) -> Interpreter<'a, C> {
+        Self::with_tape(program, Tape::Borrowed(memory), options)
+    }
+
+    /// Creates an interpreter that owns its tape. Moving the data pointer past the end extends the tape with zeros instead of failing.
+    pub fn growable(program: &'a Program, options: ExecutionOptions) -> Interpreter<'a, C> {
+        Self::with_tape(program, Tape::Growable(vec![C::default()]), options)
+    }
+
+    fn with_tape(program: &'a Program, memory: Tape<'a, C>, options: ExecutionOptions) -> Interpreter<'a, C> {
+        Interpreter {
+            instructions: &program.instructions,
+            arithmetic: program.arithmetic,
+            memory,
+            options,
+            instruction_pointer: 0,
+            data_pointer: 0,
+            input: VecDeque::new(),
+            input_closed: false,
+            bytes_read: 0,
+            steps: 0,
+            repetitions: 0,
+            origin: 0,
+            // A timeout too large to be represented never passes
+            deadline: options.timeout.and_then(|timeout| Instant::now().checked_add(timeout)),
+        }
+    }
+
+    pub fn options(&self) -> &ExecutionOptions {
+        &self.options
+    }
+
+    pub fn instruction_pointer(&self) -> usize {
+        self.instruction_pointer
+    }
+
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    /// Moves the data pointer, e.g. to continue where another program left off.
+    /// Panics if `data_pointer` is beyond the end of a borrowed tape, a growable tape grows to contain it.
+    pub fn set_data_pointer(&mut self, data_pointer: usize) {
+        assert!(self.memory.reserve(data_pointer), "The data pointer must be on the tape");
+        self.data_pointer = data_pointer;
+    }
+
+    pub fn memory(&self) -> &[C] {
+        self.memory.cells()
+    }
+
+    /// The index in `memory` of the cell the tape started with at index 0.
+    /// Only `TapeMode::Bidirectional` adds cells before it, moving all cells to higher indices.
+    pub fn origin(&self) -> usize {
+        self.origin
+    }
+
+    /// The tape of an interpreter created with `growable`, `None` if the tape is borrowed
+    pub fn into_memory(self) -> Option<Vec<C>> {
+        self.memory.into_vec()
+    }
+
+    /// The instruction the next `step` executes, `None` if the program halted
+    pub fn current_instruction(&self) -> Option<&'a Instruction> {
+        self.instructions.get(self.instruction_pointer)
+    }
+
+    /// Number of instructions executed so far
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    pub fn is_halted(&self) -> bool {
+        self.instruction_pointer >= self.instructions.len()
+    }
+
+    /// Queues a byte to be read by `,`
+    pub fn provide_input(&mut self, byte: u8) {
+        self.input.push_back(byte);
+    }
+
+    /// Marks the input as exhausted. Once the queued bytes are consumed, `,` applies the configured `EofBehavior`.
+    pub fn provide_eof(&mut self) {
+        self.input_closed = true;
+    }
+
+    /// Captures the execution state including a copy of the tape and the queued input.
+    pub fn snapshot(&self) -> Snapshot<C> {
+        Snapshot {
+            instruction_pointer: self.instruction_pointer,
+            data_pointer: self.data_pointer,
+            memory: self.memory.cells().to_vec(),
+            input: self.input.clone(),
+            input_closed: self.input_closed,
+            bytes_read: self.bytes_read,
+            steps: self.steps,
+            repetitions: self.repetitions,
+            origin: self.origin,
+        }
+    }
+
+    /// Returns to the state captured by `snapshot`, which should have been taken from an interpreter of the same program.
+    /// Panics if the tape is borrowed and the snapshot's tape has a different length.
+    pub fn restore(&mut self, snapshot: &Snapshot<C>) {
+        self.memory.restore(&snapshot.memory);
+        self.instruction_pointer = snapshot.instruction_pointer;
+        self.data_pointer = snapshot.data_pointer;
+        self.input = snapshot.input.clone();
+        self.input_closed = snapshot.input_closed;
+        self.bytes_read = snapshot.bytes_read;
+        self.steps = snapshot.steps;
+        self.repetitions = snapshot.repetitions;
+        self.origin = snapshot.origin;
+    }
+
+    /// Steps until the program writes a byte, waits for input that was not provided yet or halts, so it can be driven
+    /// from an event loop without blocking on a reader. Like `step`, it does not apply `ExecutionOptions::output_encoding`
+    /// and `ExecutionOptions::max_output`. `#` and `?` are ignored.
+    pub fn run_until_input(&mut self) -> Result<Yield, RuntimeError> {
+        loop {
+            match self.step()? {
+                StepResult::Continue | StepResult::Breakpoint | StepResult::DumpTape => {}
+                StepResult::Output(byte) => return Ok(Yield::Output(byte)),
+                StepResult::NeedsInput => return Ok(Yield::NeedsInput),
+                StepResult::Halted(halt) => return Ok(Yield::Halted(halt)),
+            }
+        }
+    }
+
+    /// Whether the timeout passed
+    // The clock is rarely looked at, keeping it out of `step` keeps the loop small
+    #[cold]
+    #[inline(never)]
+    fn timed_out(&self) -> bool {
+        self.deadline.is_some_and(|deadline| Instant::now() >= deadline)
+    }
+
+    /// Executes the current instruction. On error, the instruction pointer stays on the failing instruction.
+    // Inlining this into the loop driving the interpreter is worth about a quarter of the run time
+    #[inline]
+    pub fn step(&mut self) -> Result<StepResult, RuntimeError> {
+        let current_instruction = match self.instructions.get(self.instruction_pointer) {
+            Some(instruction) => instruction,
+            None => return Ok(StepResult::Halted(Halt::Finished)),
+        };
+
+        if let Some(max_steps) = self.options.max_steps {
+            if self.steps >= max_steps {
+                return Ok(StepResult::Halted(Halt::StepLimitReached));
+            }
+        }
+
+        // Loading the flag is cheap enough to stop right at the next instruction
+        if self.options.interrupt.is_some_and(|interrupt| interrupt.is_set()) {
+            return Ok(StepResult::Halted(Halt::Interrupted));
+        }
+
+        if self.deadline.is_some() && self.steps % TIMEOUT_CHECK_INTERVAL == 0 && self.timed_out() {
+            return Ok(StepResult::Halted(Halt::Timeout));
+        }
+
+        let data_pointer = self.data_pointer;
+
+        let mut result = StepResult::Continue;
+        match *current_instruction {
+            Instruction::IncrementPointer(n) => self.data_pointer = self.resolve(data_pointer as isize + n as isize)?,
+            Instruction::DecrementPointer(n) => self.data_pointer = self.resolve(data_pointer as isize - n as isize)?,
+            Instruction::IncrementValue(n) => self.add(data_pointer, n as isize),
+            Instruction::DecrementValue(n) => self.add(data_pointer, -(n as isize)),
+            Instruction::SetZero => self.set(data_pointer, C::default()),
+            Instruction::SetValue(value) => {
+                self.set(data_pointer, C::default());
+                self.add(data_pointer, value);
+            }
+            Instruction::FillZero { count } => {
+                let last = self.resolve(data_pointer as isize + count as isize - 1)?;
+                let cells = self.memory.cells_mut();
+                if count >= cells.len() {
+                    cells.fill(C::default());
+                } else if last >= data_pointer {
+                    cells[data_pointer..=last].fill(C::default());
+                } else {
+                    // The range wraps around the end of the tape
+                    cells[data_pointer..].fill(C::default());
+                    cells[..=last].fill(C::default());
+                }
+                self.data_pointer = last;
+            }
+            Instruction::MultiplyAdd(ref targets) => {
+                let value = self.get(data_pointer);
+
+                // The loop would not have been entered at all, so the targets must not be touched
+                if !value.is_zero() && self.wraps_positions() && self.hits_current_cell(targets.iter().map(|&(offset, _)| offset))? {
+                    // Stay on the instruction like for a scan, the loop may not end at all
+                    if !self.multiply_trip(targets.iter().copied())? {
+                        self.steps += 1;
+                        return Ok(result);
+                    }
+                } else if !value.is_zero() {
+                    // Growing the tape to the left moves the current cell, so its index is read again
+                    for &(offset, factor) in targets {
+                        let target = self.resolve(self.data_pointer as isize + offset)?;
+                        // Multiply loops are only lowered for wrapping arithmetic, where the product is congruent modulo every cell size
+                        let cell = &mut self.memory.cells_mut()[target];
+                        *cell = apply_delta(*cell, value.to_usize().wrapping_mul(factor as usize) as isize, CellArithmetic::Wrapping);
+                    }
+                    self.set(self.data_pointer, C::default());
+                }
+            }
+            Instruction::CopyTo { ref targets } => {
+                let value = self.get(data_pointer);
+
+                // Like `MultiplyAdd`, without the multiplication
+                if !value.is_zero() && self.wraps_positions() && self.hits_current_cell(targets.iter().copied())? {
+                    if !self.multiply_trip(targets.iter().map(|&offset| (offset, 1)))? {
+                        self.steps += 1;
+                        return Ok(result);
+                    }
+                } else if !value.is_zero() {
+                    for &offset in targets {
+                        let target = self.resolve(self.data_pointer as isize + offset)?;
+                        let cell = &mut self.memory.cells_mut()[target];
+                        *cell = apply_delta(*cell, value.to_usize() as isize, CellArithmetic::Wrapping);
+                    }
+                    self.set(self.data_pointer, C::default());
+                }
+            }
+            // The scan may pass the end of the tape or of the address space any number of times, so just walk it
+            Instruction::ScanRight(n) | Instruction::ScanLeft(n) if self.wraps_positions() => {
+                let step = match *current_instruction {
+                    Instruction::ScanRight(_) => n as isize,
+                    _ => -(n as isize),
+                };
+                // Stay on the instruction until a zero is found, so a scan that never ends still reaches the step limit
+                if !self.walk_scan(step)? {
+                    self.steps += 1;
+                    return Ok(result);
+                }
+            }
+            Instruction::ScanRight(n) => {
+                // Only every n-th cell is visited, so zeros in between must not stop the scan
+                let cells = self.memory.cells();
+                self.data_pointer = match cells[data_pointer..].iter().step_by(n).position(|cell| cell.is_zero()) {
+                    Some(steps) => data_pointer + steps * n,
+                    // All cells beyond the end are zero: stop at the first one that is on the stride
+                    None => self.resolve((data_pointer + (cells.len() - data_pointer + n - 1) / n * n) as isize)?,
+                };
+            }
+            Instruction::ScanLeft(n) => {
+                let cells = self.memory.cells();
+                self.data_pointer = match cells[..=data_pointer].iter().rev().step_by(n).position(|cell| cell.is_zero()) {
+                    Some(steps) => data_pointer - steps * n,
+                    // Like above, all cells before the start are zero
+                    None => self.resolve(data_pointer as isize - (data_pointer / n + 1) as isize * n as isize)?,
+                };
+            }
+            Instruction::AddAtOffset { offset, amount } => {
+                let target = self.resolve(data_pointer as isize + offset)?;
+                self.add(target, amount);
+            }
+            Instruction::LoopHead(loop_end_address) => {
+                if self.get(data_pointer).is_zero() {
+                    self.instruction_pointer = loop_end_address;
+                }
+            }
+            Instruction::LoopEnd(loop_start_address) => {
+                if !self.get(data_pointer).is_zero() {
+                    self.instruction_pointer = loop_start_address;
+                }
+            }
+            Instruction::Breakpoint => result = StepResult::Breakpoint,
+            Instruction::DumpTape => result = StepResult::DumpTape,
+            Instruction::Mark(_) => {}
+            Instruction::PutChar(n) => {
+                result = StepResult::Output(self.options.output_mask.apply(self.get(data_pointer).to_output_byte()));
+
+                // Stay on the instruction until the byte was written n times
+                self.repetitions += 1;
+                if self.repetitions < n {
+                    self.steps += 1;
+                    return Ok(result);
+                }
+                self.repetitions = 0;
+            }
+            Instruction::GetChar(n) => {
+                // Only the reads within `ExecutionOptions::input_length` take bytes from the input
+                let within_length = match self.options.input_length {
+                    Some(length) => usize::try_from(length.saturating_sub(self.bytes_read)).map_or(n, |remaining| remaining.min(n)),
+                    None => n,
+                };
+
+                // All reads happen at once, so wait until they can be satisfied
+                if self.input.len() < within_length && !self.input_closed {
+                    return Ok(StepResult::NeedsInput);
+                }
+
+                let available = within_length.min(self.input.len());
+                let last = self.input.drain(..available).next_back();
+                if let Some(byte) = last {
+                    self.set(data_pointer, C::from_input_byte(byte));
+                }
+                let reached_end = if self.options.input_length.is_some() {
+                    self.bytes_read += within_length as u64;
+                    if available < within_length {
+                        self.set(data_pointer, C::default());
+                    }
+                    within_length < n
+                } else {
+                    available < n
+                };
+                if reached_end {
+                    match self.options.eof_behavior {
+                        EofBehavior::Unchanged => {}
+                        EofBehavior::Zero => self.set(data_pointer, C::default()),
+                        EofBehavior::MinusOne => self.set(data_pointer, C::default().wrapping_sub(1)),
+                    }
+                }
+            }
+        }
+
+        // Jumps land on the matching bracket, so the instruction after it is executed next
+        self.instruction_pointer += 1;
+        self.steps += 1;
+        Ok(result)
+    }
+
+    /// Checks that `position` is on the tape (growing it if possible) and returns it as an index.
+    /// In `TapeMode::Wrap`, `position` is taken modulo the tape length instead.
+    /// In `TapeMode::Bidirectional`, growing the tape to the left also moves the data pointer along with its cell.
+    // `step` is too large for this to be inlined into it on its own, calling it costs about a tenth of the run time
+    #[inline(always)]
+    fn resolve(&mut self, position: isize) -> Result<usize, RuntimeError> {
+        if self.options.tape_mode == TapeMode::Wrap {
+            return Ok(position.rem_euclid(self.memory.cells().len() as isize) as usize);
+        }
+        if position >= 0 && (position as usize) < self.memory.cells().len() {
+            return Ok(position as usize);
+        }
+        self.resolve_outside(position)
+    }
+
+    /// The part of `resolve` for positions that are not on the tape yet
+    #[cold]
+    #[inline(never)]
+    fn resolve_outside(&mut self, position: isize) -> Result<usize, RuntimeError> {
+        // Positions on the tape are within the address space, so only the others need to be wrapped
+        if let Some(address_space) = self.options.address_space {
+            let wrapped = position.rem_euclid(address_space.get() as isize);
+            if wrapped != position {
+                return self.resolve(wrapped);
+            }
+        }
+
+        if position < 0 {
+            let added = match self.options.tape_mode {
+                TapeMode::Bidirectional => self.memory.grow_left(position.unsigned_abs()),
+                _ => 0,
+            };
+            if added == 0 {
+                return Err(RuntimeError::PointerUnderflow);
+            }
+            self.origin += added;
+            self.data_pointer += added;
+            return Ok((position + added as isize) as usize);
+        }
+        if !self.memory.reserve(position as usize) {
+            return Err(RuntimeError::PointerOverflow {
+                position: position as usize,
+            });
+        }
+        Ok(position as usize)
+    }
+
+    /// Whether positions past the end of the tape can be cells on it. Scans have to move through `resolve` one cell at a time then,
+    /// and the targets of a multiply loop can be its counter.
+    fn wraps_positions(&self) -> bool {
+        self.options.tape_mode == TapeMode::Wrap || self.options.address_space.is_some()
+    }
+
+    /// Whether one of the cells `offsets` away from the data pointer is the current cell
+    fn hits_current_cell(&mut self, offsets: impl Iterator<Item = isize>) -> Result<bool, RuntimeError> {
+        for offset in offsets {
+            if self.resolve(self.data_pointer as isize + offset)? == self.data_pointer {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    /// Runs a single iteration of the multiply loop with the `(offset, factor)` pairs `targets` and returns whether it ends.
+    /// Targets on the current cell change the counter with every iteration, so the number of iterations is not its value then.
+    fn multiply_trip(&mut self, targets: impl Iterator<Item = (isize, isize)>) -> Result<bool, RuntimeError> {
+        self.add(self.data_pointer, -1);
+        for (offset, factor) in targets {
+            let target = self.resolve(self.data_pointer as isize + offset)?;
+            self.add(target, factor);
+        }
+        Ok(self.get(self.data_pointer).is_zero())
+    }
+
+    /// Moves the data pointer by `step` until it is on a zero cell, but gives up after a round of the tape and returns `false` then.
+    /// Without a zero on the way, the scan may never end, giving up lets the caller count it against the limits of the execution.
+    fn walk_scan(&mut self, step: isize) -> Result<bool, RuntimeError> {
+        for _ in 0..self.memory.cells().len() {
+            if self.get(self.data_pointer).is_zero() {
+                return Ok(true);
+            }
+            self.data_pointer = self.resolve(self.data_pointer as isize + step)?;
+        }
+        Ok(self.get(self.data_pointer).is_zero())
+    }
+
+    fn get(&self, index: usize) -> C {
+        self.memory.cells()[index]
+    }
+
+    fn set(&mut self, index: usize, value: C) {
+        self.memory.cells_mut()[index] = value;
+    }
+
+    /// Adds `amount` to a cell using the program's arithmetic
+    // Like `resolve`
+    #[inline(always)]
+    fn add(&mut self, index: usize, amount: isize) {
+        let cell = &mut self.memory.cells_mut()[index];
+        *cell = apply_delta(*cell, amount, self.arithmetic);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::num::NonZeroUsize;
+    use std::sync::atomic::AtomicBool;
+    use std::time::Duration;
+
+    use super::{Interpreter, StepResult, Yield};
+    use crate::brainfuck::testing::{unoptimized, wrap};
+    use crate::brainfuck::{CellArithmetic, ExecutionOptions, Halt, Interrupt, ParseOptions, Program, RuntimeError, TapeMode};
+
+    fn run(code: &str, memory: &mut [u8], options: &ExecutionOptions) -> Halt {
+        Program::parse(code).unwrap().run_io_with_options(memory, io::empty(), io::sink(), options).unwrap()
+    }
+
+    /// Steps until the program halts and returns what it wrote, `,` sees the end of input
+    fn finish(interpreter: &mut Interpreter) -> Vec<u8> {
+        let mut output = Vec::new();
+        loop {
+            match interpreter.step().unwrap() {
+                StepResult::Output(byte) => output.push(byte),
+                StepResult::NeedsInput => interpreter.provide_eof(),
+                StepResult::Halted(_) => return output,
+                StepResult::Continue | StepResult::Breakpoint | StepResult::DumpTape => {}
+            }
+        }
+    }
+
+    /// Takes a snapshot after `steps` steps, runs to the end and asserts that restoring the snapshot resumes from there
+    fn assert_resumes_from_snapshot(interpreter: &mut Interpreter, steps: u64) {
+        for _ in 0..steps {
+            interpreter.step().unwrap();
+        }
+        let snapshot = interpreter.snapshot();
+        let state = (interpreter.data_pointer(), interpreter.memory().to_vec(), interpreter.origin());
+        assert_eq!(snapshot.steps(), steps);
+        let output = finish(interpreter);
+        assert_ne!(interpreter.steps(), steps);
+
+        interpreter.restore(&snapshot);
+        assert_eq!((interpreter.data_pointer(), interpreter.memory().to_vec(), interpreter.origin()), state);
+        assert_eq!(interpreter.steps(), steps);
+        assert_eq!(interpreter.instruction_pointer(), snapshot.instruction_pointer());
+        assert_eq!(finish(interpreter), output);
+    }
+
+    #[test]
+    fn run_until_input_resumes_with_the_next_chunk_of_input() {
+        let program = Program::parse(">,[.[-],]<+.").unwrap();
+        let mut memory = [0u8; 2];
+        let mut interpreter = Interpreter::new(&program, &mut memory);
+        assert_eq!(interpreter.run_until_input().unwrap(), Yield::NeedsInput);
+
+        let mut yields = Vec::new();
+        for chunk in [&b"ab"[..], b"c"] {
+            for &byte in chunk {
+                interpreter.provide_input(byte);
+            }
+            loop {
+                match interpreter.run_until_input().unwrap() {
+                    Yield::NeedsInput => break,
+                    output => yields.push(output),
+                }
+            }
+            // Waiting for input leaves the pointer on the cell the next byte is read into
+            assert_eq!(interpreter.data_pointer(), 1);
+        }
+        interpreter.provide_eof();
+        yields.push(interpreter.run_until_input().unwrap());
+        yields.push(interpreter.run_until_input().unwrap());
+        yields.push(interpreter.run_until_input().unwrap());
+
+        let finished = Yield::Halted(Halt::Finished);
+        assert_eq!(yields, [Yield::Output(b'a'), Yield::Output(b'b'), Yield::Output(b'c'), Yield::Output(1), finished, finished]);
+        assert_eq!(memory, [1, 0]);
+    }
+
+    #[test]
+    fn restored_snapshots_resume_where_they_were_taken() {
+        let program = Program::parse_with_options("++++[>+++<-]>[>++>+<<-]>.>.<<,.", &unoptimized()).unwrap();
+        let mut memory = [0u8; 4];
+        assert_resumes_from_snapshot(&mut Interpreter::new(&program, &mut memory), 20);
+
+        // The snapshot of a growing tape is shorter than the tape at the end and starts at a different origin
+        let program = Program::parse_with_options(">+<<+<.>>>>>>.", &unoptimized()).unwrap();
+        let bidirectional = ExecutionOptions {
+            tape_mode: TapeMode::Bidirectional,
+            ..ExecutionOptions::default()
+        };
+        let mut interpreter = Interpreter::growable(&program, bidirectional);
+        assert_resumes_from_snapshot(&mut interpreter, 4);
+        assert_eq!(interpreter.origin(), 2);
+        assert_eq!(interpreter.into_memory().unwrap(), [0, 1, 0, 1, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn saturating_cells_stop_at_both_bounds() {
+        let saturating = ParseOptions {
+            arithmetic: CellArithmetic::Saturating,
+            ..ParseOptions::default()
+        };
+        let program = Program::parse_with_options(&format!("---.{}.>+++{}.", "+".repeat(300), "-".repeat(10)), &saturating).unwrap();
+        assert_eq!(program.run_to_string(&mut [0u8; 2], b"").unwrap(), [0, 255, 0]);
+
+        let mut memory = [0u16; 2];
+        program.run_to_string(&mut memory, b"").unwrap();
+        assert_eq!(memory, [300, 0]);
+    }
+
+    #[test]
+    fn pointer_walking_off_either_end_wraps() {
+        let mut memory = [0u8; 3];
+        run(">>>+", &mut memory, &wrap());
+        assert_eq!(memory, [1, 0, 0]);
+
+        let mut memory = [0u8; 3];
+        run("<+<<<++", &mut memory, &wrap());
+        assert_eq!(memory, [0, 0, 3]);
+    }
+
+    #[test]
+    fn scans_wrap_around_the_tape() {
+        let mut memory = [0u8; 4];
+        assert_eq!(run("+>>+>+<[>]++", &mut memory, &wrap()), Halt::Finished);
+        assert_eq!(memory, [1, 2, 1, 1]);
+
+        let mut memory = [0u8; 4];
+        assert_eq!(run("+>+>>+<<<[<]++", &mut memory, &wrap()), Halt::Finished);
+        assert_eq!(memory, [1, 1, 2, 1]);
+    }
+
+    #[test]
+    fn scans_wrap_around_the_address_space() {
+        let address_space = ExecutionOptions {
+            address_space: NonZeroUsize::new(16),
+            ..ExecutionOptions::default()
+        };
+        let program = Program::parse("-<-[>]+").unwrap();
+        let mut memory = [0u8; 16];
+        let mut data_pointer = 0;
+        program.run_io_at(&mut memory, &mut data_pointer, io::empty(), io::sink(), &address_space).unwrap();
+        assert_eq!(data_pointer, 1);
+        assert_eq!(memory[..2], [255, 1]);
+        assert_eq!(memory[15], 255);
+
+        // Positions past the end of a shorter tape are not cells
+        let fragment = ParseOptions {
+            fragment: true,
+            ..ParseOptions::default()
+        };
+        let program = Program::parse_with_options("[>]", &fragment).unwrap();
+        let error = program.run_io_with_options(&mut [1u8; 4], io::empty(), io::sink(), &address_space).unwrap_err();
+        assert!(matches!(error, RuntimeError::PointerOverflow { position: 4 }));
+    }
+
+    #[test]
+    fn scans_without_a_zero_stop_at_the_limits() {
+        let code = "+[>+<-]+>+<[>]";
+        let options = ExecutionOptions {
+            max_steps: Some(1000),
+            timeout: Some(Duration::from_millis(100)),
+            ..wrap()
+        };
+        assert_eq!(run(code, &mut [0u8; 2], &options), Halt::StepLimitReached);
+
+        let options = ExecutionOptions {
+            timeout: Some(Duration::from_millis(100)),
+            ..wrap()
+        };
+        assert_eq!(run(code, &mut [0u8; 2], &options), Halt::Timeout);
+    }
+
+    #[test]
+    fn multiply_loops_may_change_their_own_counter_on_a_short_tape() {
+        // A target on the counter cancels its decrement or changes it by a different step in every iteration
+        let loops = [("+[->+<]", 1), ("++[->++<]", 1), ("+++[->>+<<]", 2), ("+++[->+>-<<]", 1), ("+[->+>>+<<<]", 3)];
+        for (code, length) in loops {
+            let address_space = ExecutionOptions {
+                address_space: NonZeroUsize::new(length),
+                ..ExecutionOptions::default()
+            };
+            for options in [wrap(), address_space] {
+                let options = ExecutionOptions {
+                    max_steps: Some(100_000),
+                    ..options
+                };
+                let mut memory = vec![0u8; length];
+                let halt = run(code, &mut memory, &options);
+                let mut unoptimized_memory = vec![0u8; length];
+                let unoptimized_halt = Program::parse_with_options(code, &unoptimized())
+                    .unwrap()
+                    .run_io_with_options(&mut unoptimized_memory, io::empty(), io::sink(), &options)
+                    .unwrap();
+                assert_eq!(halt, unoptimized_halt, "{:?} on {:?}", code, options.tape_mode);
+                // The step limit is reached at a different point of the loop
+                if halt == Halt::Finished {
+                    assert_eq!(memory, unoptimized_memory, "{:?} on {:?}", code, options.tape_mode);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn set_interrupts_stop_before_the_next_instruction() {
+        static INTERRUPTED: AtomicBool = AtomicBool::new(true);
+        let options = ExecutionOptions {
+            interrupt: Some(Interrupt::new(&INTERRUPTED)),
+            ..ExecutionOptions::default()
+        };
+        let mut memory = [0u8; 2];
+        assert_eq!(run("+>+", &mut memory, &options), Halt::Interrupted);
+        assert_eq!(memory, [0, 0]);
+    }
+}
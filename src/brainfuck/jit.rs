@@ -0,0 +1,650 @@
+//! Compiles the instructions of a `Program` to machine code using cranelift.
+//! The pointer is kept in a register and loops become native branches, the tape mode is checked inline on every pointer move.
+//! `,` and `.` call back into Rust to use the reader and writer.
+
+use std::io::{self, Read, Write};
+use std::num::NonZeroUsize;
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, Block, InstBuilder, MemFlags, Signature, Type, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::Module;
+
+use super::{EofBehavior, ExecutionOptions, Halt, Instruction, OutputEncoding, OutputMask, RuntimeError, Signedness, TapeMode};
+
+// Return values of the compiled function
+const FINISHED: i64 = 0;
+const POINTER_OVERFLOW: i64 = 1;
+const POINTER_UNDERFLOW: i64 = 2;
+const IO_ERROR: i64 = 3;
+
+/// Signature of the compiled program: tape, tape length, I/O context and where to store the position of an overflow
+type CompiledProgram = unsafe extern "C" fn(*mut u8, isize, *mut Context, *mut isize) -> i64;
+
+/// Passed to the callbacks for `,` and `.`
+struct Context<'a> {
+    input: io::Bytes<io::BufReader<&'a mut dyn Read>>,
+    output: &'a mut dyn Write,
+    eof_behavior: EofBehavior,
+    output_encoding: OutputEncoding,
+    output_mask: OutputMask,
+    signedness: Signedness,
+    error: Option<io::Error>,
+}
+
+extern "C" fn put_char(context: *mut Context, byte: u32, count: usize) -> u32 {
+    let context = unsafe { &mut *context };
+    for _ in 0..count {
+        let written = match context.output_encoding {
+            OutputEncoding::Byte => context.output.write_all(&[context.output_mask.apply(byte as u8)]),
+            OutputEncoding::Decimal => write!(context.output, "{} ", context.signedness.render(byte as u8)),
+        };
+        if let Err(error) = written {
+            context.error = Some(error);
+            return 1;
+        }
+    }
+    0
+}
+
+extern "C" fn get_char(context: *mut Context, cell: *mut u8, count: usize) -> u32 {
+    let context = unsafe { &mut *context };
+    let cell = unsafe { &mut *cell };
+
+    // Make sure a prompt is visible before blocking on input
+    if let Err(error) = context.output.flush() {
+        context.error = Some(error);
+        return 1;
+    }
+
+    for _ in 0..count {
+        match context.input.next() {
+            Some(Ok(byte)) => *cell = byte,
+            Some(Err(error)) => {
+                context.error = Some(error);
+                return 1;
+            }
+            // All further reads would see the end of input as well
+            None => {
+                match context.eof_behavior {
+                    EofBehavior::Unchanged => {}
+                    EofBehavior::Zero => *cell = 0,
+                    EofBehavior::MinusOne => *cell = 255,
+                }
+                break;
+            }
+        }
+    }
+    0
+}
+
+pub(super) fn run<R: Read, W: Write>(
+    instructions: &[Instruction],
+    memory: &mut [u8],
+    mut input: R,
+    mut output: W,
+    options: &ExecutionOptions,
+) -> Result<Halt, RuntimeError> {
+    // The compiled code accesses the current cell without a check, and a wrapping tape of no cells would divide by zero
+    if memory.is_empty() && !instructions.is_empty() {
+        return Err(RuntimeError::PointerOverflow { position: 0 });
+    }
+
+    let mut module = create_module();
+    let compiled = compile(&mut module, instructions, options.tape_mode, options.address_space);
+
+    let mut context = Context {
+        input: io::BufReader::new(&mut input as &mut dyn Read).bytes(),
+        output: &mut output,
+        eof_behavior: options.eof_behavior,
+        output_encoding: options.output_encoding,
+        output_mask: options.output_mask,
+        signedness: options.signedness,
+        error: None,
+    };
+    let mut overflow_position = 0;
+
+    let status = unsafe {
+        compiled(
+            memory.as_mut_ptr(),
+            memory.len() as isize,
+            &mut context,
+            &mut overflow_position,
+        )
+    };
+
+    let result = match status {
+        FINISHED => Ok(Halt::Finished),
+        POINTER_OVERFLOW => Err(RuntimeError::PointerOverflow {
+            position: overflow_position as usize,
+        }),
+        POINTER_UNDERFLOW => Err(RuntimeError::PointerUnderflow),
+        IO_ERROR => Err(RuntimeError::Io(context.error.take().unwrap())),
+        _ => unreachable!("Unknown status {} returned by the compiled program", status),
+    };
+    drop(context);
+
+    output.flush()?;
+    // The compiled program is not called again
+    unsafe { module.free_memory() };
+    result
+}
+
+fn create_module() -> JITModule {
+    let mut flags = settings::builder();
+    flags.set("opt_level", "speed").unwrap();
+    let isa = cranelift_native::builder()
+        .expect("The host is not supported by cranelift")
+        .finish(settings::Flags::new(flags))
+        .unwrap();
+
+    JITModule::new(JITBuilder::with_isa(isa, cranelift_module::default_libcall_names()))
+}
+
+fn compile(
+    module: &mut JITModule,
+    instructions: &[Instruction],
+    tape_mode: TapeMode,
+    address_space: Option<NonZeroUsize>,
+) -> CompiledProgram {
+    let pointer_type = module.target_config().pointer_type();
+
+    let mut context = module.make_context();
+    let signature = &mut context.func.signature;
+    signature.params.extend([AbiParam::new(pointer_type); 4].iter().cloned());
+    signature.returns.push(AbiParam::new(types::I64));
+
+    let mut put_char_signature = module.make_signature();
+    put_char_signature.params.push(AbiParam::new(pointer_type));
+    put_char_signature.params.push(AbiParam::new(types::I32));
+    put_char_signature.params.push(AbiParam::new(pointer_type));
+    put_char_signature.returns.push(AbiParam::new(types::I32));
+
+    let mut get_char_signature = module.make_signature();
+    get_char_signature.params.push(AbiParam::new(pointer_type));
+    get_char_signature.params.push(AbiParam::new(pointer_type));
+    get_char_signature.params.push(AbiParam::new(pointer_type));
+    get_char_signature.returns.push(AbiParam::new(types::I32));
+
+    let mut builder_context = FunctionBuilderContext::new();
+    let mut builder = FunctionBuilder::new(&mut context.func, &mut builder_context);
+
+    let entry = builder.create_block();
+    builder.append_block_params_for_function_params(entry);
+    builder.switch_to_block(entry);
+    let params = builder.block_params(entry).to_vec();
+
+    let data_pointer = builder.declare_var(pointer_type);
+    let zero = builder.ins().iconst(pointer_type, 0);
+    builder.def_var(data_pointer, zero);
+
+    let out_of_bounds = builder.create_block();
+    builder.append_block_param(out_of_bounds, pointer_type);
+    let io_error = builder.create_block();
+
+    let mut translator = Translator {
+        builder,
+        pointer_type,
+        tape_mode,
+        address_space,
+        data_pointer,
+        memory: params[0],
+        length: params[1],
+        context: params[2],
+        out_of_bounds,
+        io_error,
+        put_char_signature,
+        get_char_signature,
+    };
+    translator.translate(instructions);
+    translator.finish(params[3]);
+
+    let id = module
+        .declare_anonymous_function(&context.func.signature)
+        .unwrap();
+    module.define_function(id, &mut context).unwrap();
+    module.clear_context(&mut context);
+    module.finalize_definitions().unwrap();
+
+    let code = module.get_finalized_function(id);
+    unsafe { std::mem::transmute::<*const u8, CompiledProgram>(code) }
+}
+
+struct Translator<'a> {
+    builder: FunctionBuilder<'a>,
+    pointer_type: Type,
+    tape_mode: TapeMode,
+    address_space: Option<NonZeroUsize>,
+    data_pointer: Variable,
+    memory: Value,
+    length: Value,
+    context: Value,
+    /// Reached with the position outside of a strict tape
+    out_of_bounds: Block,
+    io_error: Block,
+    put_char_signature: Signature,
+    get_char_signature: Signature,
+}
+
+impl<'a> Translator<'a> {
+    fn translate(&mut self, instructions: &[Instruction]) {
+        // Body and exit block of every open loop
+        let mut loops = Vec::new();
+
+        for instruction in instructions {
+            match *instruction {
+                Instruction::IncrementPointer(n) => self.move_pointer(n as isize),
+                Instruction::DecrementPointer(n) => self.move_pointer(-(n as isize)),
+                Instruction::IncrementValue(n) => self.add_at_offset(0, n as u8),
+                Instruction::DecrementValue(n) => self.add_at_offset(0, (n as u8).wrapping_neg()),
+                Instruction::SetZero => self.set_current(0),
+                Instruction::SetValue(value) => self.set_current(value as u8),
+                Instruction::FillZero { count } => self.fill_zero(count),
+                Instruction::AddAtOffset { offset, amount } => self.add_at_offset(offset, amount as u8),
+                Instruction::MultiplyAdd(ref targets) => {
+                    let body = self.builder.create_block();
+                    let next = self.builder.create_block();
+                    let value = self.load_current();
+                    self.builder.ins().brif(value, body, &[], next, &[]);
+
+                    self.builder.switch_to_block(body);
+                    if self.wraps_positions() {
+                        self.multiply_trips_if_counter_is_target(targets, next);
+                    }
+                    for &(offset, factor) in targets {
+                        let product = self.builder.ins().imul_imm(value, factor as u8 as i64);
+                        let address = self.address_at_offset(offset);
+                        self.add_to(address, product);
+                    }
+                    self.set_current(0);
+                    self.builder.ins().jump(next, &[]);
+
+                    self.builder.switch_to_block(next);
+                }
+                Instruction::CopyTo { ref targets } => {
+                    let body = self.builder.create_block();
+                    let next = self.builder.create_block();
+                    let value = self.load_current();
+                    self.builder.ins().brif(value, body, &[], next, &[]);
+
+                    self.builder.switch_to_block(body);
+                    if self.wraps_positions() {
+                        let targets: Vec<(isize, isize)> = targets.iter().map(|&offset| (offset, 1)).collect();
+                        self.multiply_trips_if_counter_is_target(&targets, next);
+                    }
+                    for &offset in targets {
+                        let address = self.address_at_offset(offset);
+                        self.add_to(address, value);
+                    }
+                    self.set_current(0);
+                    self.builder.ins().jump(next, &[]);
+
+                    self.builder.switch_to_block(next);
+                }
+                Instruction::ScanRight(n) => self.scan(n as isize),
+                Instruction::ScanLeft(n) => self.scan(-(n as isize)),
+                Instruction::LoopHead(_) => {
+                    let body = self.builder.create_block();
+                    let exit = self.builder.create_block();
+                    let value = self.load_current();
+                    self.builder.ins().brif(value, body, &[], exit, &[]);
+
+                    self.builder.switch_to_block(body);
+                    loops.push((body, exit));
+                }
+                Instruction::LoopEnd(_) => {
+                    let (body, exit) = loops.pop().unwrap();
+                    let value = self.load_current();
+                    self.builder.ins().brif(value, body, &[], exit, &[]);
+
+                    self.builder.switch_to_block(exit);
+                }
+                Instruction::PutChar(n) => {
+                    let value = self.load_current();
+                    let value = self.builder.ins().uextend(types::I32, value);
+                    let count = self.builder.ins().iconst(self.pointer_type, n as i64);
+                    let status = self.call(put_char as *const (), self.put_char_signature.clone(), &[value, count]);
+                    self.check_io(status);
+                }
+                Instruction::GetChar(n) => {
+                    let address = self.address_at_offset(0);
+                    let count = self.builder.ins().iconst(self.pointer_type, n as i64);
+                    let status = self.call(get_char as *const (), self.get_char_signature.clone(), &[address, count]);
+                    self.check_io(status);
+                }
+                Instruction::Breakpoint | Instruction::DumpTape | Instruction::Mark(_) => {}
+            }
+        }
+    }
+
+    fn finish(mut self, overflow_position: Value) {
+        let finished = self.builder.ins().iconst(types::I64, FINISHED);
+        self.builder.ins().return_(&[finished]);
+
+        self.builder.switch_to_block(self.out_of_bounds);
+        let position = self.builder.block_params(self.out_of_bounds)[0];
+        let underflow = self.builder.create_block();
+        let overflow = self.builder.create_block();
+        let is_negative = self.builder.ins().icmp_imm(IntCC::SignedLessThan, position, 0);
+        self.builder.ins().brif(is_negative, underflow, &[], overflow, &[]);
+
+        self.builder.switch_to_block(underflow);
+        let status = self.builder.ins().iconst(types::I64, POINTER_UNDERFLOW);
+        self.builder.ins().return_(&[status]);
+
+        self.builder.switch_to_block(overflow);
+        self.builder.ins().store(MemFlags::trusted(), position, overflow_position, 0);
+        let status = self.builder.ins().iconst(types::I64, POINTER_OVERFLOW);
+        self.builder.ins().return_(&[status]);
+
+        self.builder.switch_to_block(self.io_error);
+        let status = self.builder.ins().iconst(types::I64, IO_ERROR);
+        self.builder.ins().return_(&[status]);
+
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+    }
+
+    /// The index of the cell `offset` cells away from the data pointer, applying the tape mode
+    fn resolve(&mut self, offset: isize) -> Value {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let position = self.builder.ins().iadd_imm(data_pointer, offset as i64);
+
+        match self.tape_mode {
+            TapeMode::Wrap => self.euclidean_remainder(position, self.length),
+            // The compiled code only runs on borrowed tapes, which cannot grow to the left
+            TapeMode::Strict | TapeMode::Bidirectional => {
+                // Negative positions are huge when compared unsigned
+                let in_bounds = self.builder.create_block();
+                let outside = self.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, position, self.length);
+                let address_space = match self.address_space {
+                    Some(address_space) => address_space,
+                    None => {
+                        self.builder
+                            .ins()
+                            .brif(outside, self.out_of_bounds, &[position.into()], in_bounds, &[]);
+                        self.builder.switch_to_block(in_bounds);
+                        return position;
+                    }
+                };
+
+                // Like in the interpreter, positions on the tape are within the address space and only the others are wrapped
+                let wrap = self.builder.create_block();
+                self.builder.set_cold_block(wrap);
+                self.builder.append_block_param(in_bounds, self.pointer_type);
+                self.builder.ins().brif(outside, wrap, &[], in_bounds, &[position.into()]);
+
+                self.builder.switch_to_block(wrap);
+                let address_space = self.builder.ins().iconst(self.pointer_type, address_space.get() as i64);
+                let wrapped = self.euclidean_remainder(position, address_space);
+                let outside = self.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, wrapped, self.length);
+                self.builder
+                    .ins()
+                    .brif(outside, self.out_of_bounds, &[wrapped.into()], in_bounds, &[wrapped.into()]);
+
+                self.builder.switch_to_block(in_bounds);
+                self.builder.block_params(in_bounds)[0]
+            }
+        }
+    }
+
+    /// Whether positions past the end of the tape can be cells on it, like in the interpreter
+    fn wraps_positions(&self) -> bool {
+        self.tape_mode == TapeMode::Wrap || self.address_space.is_some()
+    }
+
+    /// Runs the multiply loop with the `(offset, factor)` pairs `targets` one iteration at a time and continues at `next` if a
+    /// target is the current cell, which changes the counter in every iteration. Otherwise the code continues in a new block.
+    fn multiply_trips_if_counter_is_target(&mut self, targets: &[(isize, isize)], next: Block) {
+        let data_pointer = self.builder.use_var(self.data_pointer);
+        let mut hit = self.builder.ins().iconst(types::I8, 0);
+        for &(offset, _) in targets {
+            let target = self.resolve(offset);
+            let is_current = self.builder.ins().icmp(IntCC::Equal, target, data_pointer);
+            hit = self.builder.ins().bor(hit, is_current);
+        }
+        let head = self.builder.create_block();
+        let trip = self.builder.create_block();
+        let distinct = self.builder.create_block();
+        self.builder.ins().brif(hit, head, &[], distinct, &[]);
+
+        self.builder.switch_to_block(head);
+        let value = self.load_current();
+        self.builder.ins().brif(value, trip, &[], next, &[]);
+
+        self.builder.switch_to_block(trip);
+        self.add_at_offset(0, u8::MAX);
+        for &(offset, factor) in targets {
+            self.add_at_offset(offset, factor as u8);
+        }
+        self.builder.ins().jump(head, &[]);
+
+        self.builder.switch_to_block(distinct);
+    }
+
+    fn euclidean_remainder(&mut self, position: Value, modulus: Value) -> Value {
+        // `srem` keeps the sign of the position
+        let remainder = self.builder.ins().srem(position, modulus);
+        let wrapped = self.builder.ins().iadd(remainder, modulus);
+        let is_negative = self.builder.ins().icmp_imm(IntCC::SignedLessThan, remainder, 0);
+        self.builder.ins().select(is_negative, wrapped, remainder)
+    }
+
+    fn move_pointer(&mut self, offset: isize) {
+        let position = self.resolve(offset);
+        self.builder.def_var(self.data_pointer, position);
+    }
+
+    fn address_at_offset(&mut self, offset: isize) -> Value {
+        let index = if offset == 0 {
+            self.builder.use_var(self.data_pointer)
+        } else {
+            self.resolve(offset)
+        };
+        self.builder.ins().iadd(self.memory, index)
+    }
+
+    fn load_current(&mut self) -> Value {
+        let address = self.address_at_offset(0);
+        self.builder.ins().load(types::I8, MemFlags::trusted(), address, 0)
+    }
+
+    fn set_current(&mut self, value: u8) {
+        let address = self.address_at_offset(0);
+        let value = self.builder.ins().iconst(types::I8, value as i64);
+        self.builder.ins().store(MemFlags::trusted(), value, address, 0);
+    }
+
+    /// Zeroes the cells one by one, so the bounds and the wrapping of the tape apply to every step like for `[-]>[-]`
+    fn fill_zero(&mut self, count: usize) {
+        let head = self.builder.create_block();
+        let body = self.builder.create_block();
+        let exit = self.builder.create_block();
+        self.builder.append_block_param(head, types::I64);
+
+        self.set_current(0);
+        let remaining = self.builder.ins().iconst(types::I64, count as i64 - 1);
+        self.builder.ins().jump(head, &[remaining.into()]);
+
+        self.builder.switch_to_block(head);
+        let remaining = self.builder.block_params(head)[0];
+        self.builder.ins().brif(remaining, body, &[], exit, &[]);
+
+        self.builder.switch_to_block(body);
+        self.move_pointer(1);
+        self.set_current(0);
+        let remaining = self.builder.ins().iadd_imm(remaining, -1);
+        self.builder.ins().jump(head, &[remaining.into()]);
+
+        self.builder.switch_to_block(exit);
+    }
+
+    fn add_at_offset(&mut self, offset: isize, amount: u8) {
+        let address = self.address_at_offset(offset);
+        let amount = self.builder.ins().iconst(types::I8, amount as i64);
+        self.add_to(address, amount);
+    }
+
+    fn add_to(&mut self, address: Value, amount: Value) {
+        let value = self.builder.ins().load(types::I8, MemFlags::trusted(), address, 0);
+        let value = self.builder.ins().iadd(value, amount);
+        self.builder.ins().store(MemFlags::trusted(), value, address, 0);
+    }
+
+    fn scan(&mut self, step: isize) {
+        let head = self.builder.create_block();
+        let body = self.builder.create_block();
+        let exit = self.builder.create_block();
+        self.builder.ins().jump(head, &[]);
+
+        self.builder.switch_to_block(head);
+        let value = self.load_current();
+        self.builder.ins().brif(value, body, &[], exit, &[]);
+
+        self.builder.switch_to_block(body);
+        self.move_pointer(step);
+        self.builder.ins().jump(head, &[]);
+
+        self.builder.switch_to_block(exit);
+    }
+
+    /// Calls one of the I/O callbacks with the context and `argument`
+    fn call(&mut self, callback: *const (), signature: Signature, arguments: &[Value]) -> Value {
+        let signature = self.builder.import_signature(signature);
+        let callback = self.builder.ins().iconst(self.pointer_type, callback as i64);
+        let arguments: Vec<Value> = std::iter::once(self.context).chain(arguments.iter().copied()).collect();
+        let call = self.builder.ins().call_indirect(signature, callback, &arguments);
+        self.builder.inst_results(call)[0]
+    }
+
+    fn check_io(&mut self, status: Value) {
+        let next = self.builder.create_block();
+        self.builder.ins().brif(status, self.io_error, &[], next, &[]);
+        self.builder.switch_to_block(next);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::num::NonZeroUsize;
+
+    use crate::brainfuck::testing::wrap;
+    use crate::brainfuck::{EofBehavior, ExecutionOptions, Halt, OutputEncoding, OutputMask, Program, RuntimeError, Signedness};
+
+    #[test]
+    fn runs_like_the_interpreter() {
+        let programs = [
+            include_str!("../test/hello.bf"),
+            ",.,.,.",
+            ",>,<[->+<]>.",
+            ",,,.>-.",
+            "++++[>++++<-]>[>+>++>+++<<<-]>>>[<]>[>]<.[-]<[>+<-]>.",
+            "+>+>+>+<<<[>]<[<]>.",
+            "+[>+]",
+            "<",
+        ];
+        let decimal = ExecutionOptions {
+            output_encoding: OutputEncoding::Decimal,
+            signedness: Signedness::Signed,
+            ..ExecutionOptions::default()
+        };
+        let option_sets = [
+            ExecutionOptions::default(),
+            wrap(),
+            decimal,
+            ExecutionOptions {
+                eof_behavior: EofBehavior::Zero,
+                ..ExecutionOptions::default()
+            },
+            ExecutionOptions {
+                eof_behavior: EofBehavior::MinusOne,
+                output_mask: OutputMask::SevenBit,
+                ..ExecutionOptions::default()
+            },
+            ExecutionOptions {
+                address_space: NonZeroUsize::new(8),
+                ..ExecutionOptions::default()
+            },
+        ];
+        for code in programs {
+            let program = Program::parse(code).unwrap();
+            for options in &option_sets {
+                let run = |jit: bool| {
+                    let mut memory = vec![0u8; 8];
+                    let mut output = Vec::new();
+                    let result = if jit {
+                        program.jit_run_io_with_options(&mut memory, &b"ab"[..], &mut output, options)
+                    } else {
+                        program.run_io_with_options(&mut memory, &b"ab"[..], &mut output, options)
+                    };
+                    (format!("{:?}", result), output, memory)
+                };
+                assert_eq!(run(true), run(false), "{:?} with {:?}", code, options);
+            }
+        }
+    }
+
+    #[test]
+    fn address_space_only_wraps_positions_off_the_tape() {
+        let programs = [
+            "+>>>+>>>+<<<<<<<<-.",
+            "<+>>+.",
+            ">>>>>>>+",
+            "+>+>>+[>]+<[<]>.",
+            "+++[->>>>>+++<<<<<]>>>>>.",
+        ];
+        for code in programs {
+            let program = Program::parse(code).unwrap();
+            for (length, address_space) in [(6, 3), (6, 4), (2, 4)] {
+                let options = ExecutionOptions {
+                    address_space: NonZeroUsize::new(address_space),
+                    ..ExecutionOptions::default()
+                };
+                let run = |jit: bool| {
+                    let mut memory = vec![0u8; length];
+                    let mut output = Vec::new();
+                    let result = if jit {
+                        program.jit_run_io_with_options(&mut memory, io::empty(), &mut output, &options)
+                    } else {
+                        program.run_io_with_options(&mut memory, io::empty(), &mut output, &options)
+                    };
+                    (format!("{:?}", result), output, memory)
+                };
+                assert_eq!(run(true), run(false), "{:?} on {} cells with an address space of {}", code, length, address_space);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_tapes_are_rejected() {
+        let program = Program::parse("+").unwrap();
+        for options in [ExecutionOptions::default(), wrap()] {
+            let result = program.jit_run_io_with_options(&mut [], io::empty(), io::sink(), &options);
+            assert!(matches!(result, Err(RuntimeError::PointerOverflow { position: 0 })), "{:?}", result);
+        }
+        let empty = Program::parse("").unwrap();
+        assert!(matches!(empty.jit_run_io_with_options(&mut [], io::empty(), io::sink(), &wrap()), Ok(Halt::Finished)));
+    }
+
+    #[test]
+    fn multiply_loops_may_change_their_own_counter_on_a_short_tape() {
+        // The counter of these loops still reaches zero, the interpreter checks the ones that never end
+        for (code, length) in [("++[->++<]", 1), ("+++[->+>-<<]", 1), ("++++[->>+++<<]", 2), ("++[->+>++<<]", 2)] {
+            let address_space = ExecutionOptions {
+                address_space: NonZeroUsize::new(length),
+                ..ExecutionOptions::default()
+            };
+            for options in [wrap(), address_space] {
+                let program = Program::parse(code).unwrap();
+                let mut memory = vec![0u8; length];
+                program.jit_run_io_with_options(&mut memory, io::empty(), io::sink(), &options).unwrap();
+                let mut interpreted_memory = vec![0u8; length];
+                program.run_io_with_options(&mut interpreted_memory, io::empty(), io::sink(), &options).unwrap();
+                assert_eq!(memory, interpreted_memory, "{:?} on {:?}", code, options.tape_mode);
+            }
+        }
+    }
+}
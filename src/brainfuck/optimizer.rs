@@ -0,0 +1,823 @@
+//! Peephole optimizations on the run-length encoded instructions.
+//! They run before the loops are bound, so loop addresses must not be relied upon here.
+//! Every instruction carries the range of source bytes it was lowered from, which the passes merge when they combine instructions.
+
+use std::ops::Range;
+
+use super::{CellArithmetic, Instruction, OpCode, ParseOptions, SourceSpan, Unbound};
+
+pub(super) type Spanned = (Instruction<Unbound>, Range<usize>);
+
+pub(super) fn optimize(instructions: Vec<Spanned>, options: &ParseOptions) -> Vec<Spanned> {
+    let wrapping = options.arithmetic == CellArithmetic::Wrapping;
+
+    let instructions = optimize_clear_loops(&instructions, wrapping);
+    let instructions = optimize_set_values(&instructions, wrapping);
+    let instructions = optimize_fill_zero(&instructions);
+    // Saturating a target in every iteration cannot be expressed as a single multiplication
+    let instructions = if wrapping {
+        optimize_multiply_loops(&instructions)
+    } else {
+        instructions
+    };
+    let instructions = optimize_scan_loops(&instructions);
+    // Saturating changes to the counter do not add up to the same step in every iteration
+    let instructions = if wrapping {
+        merge_runs(&unroll_counted_loops(&instructions), options.cell_size())
+    } else {
+        instructions
+    };
+    let instructions = optimize_offsets(&instructions);
+    // Sums of saturating changes depend on the cell size
+    let instructions = if wrapping {
+        propagate_constants(&instructions)
+    } else {
+        instructions
+    };
+    remove_dead_loops(&instructions, !options.fragment)
+}
+
+/// Removes adjacent op codes that undo each other, like `+-` or `<>`, before they are run-length encoded.
+/// Removing a pair can make its neighbours adjacent, so `++>><<--` vanishes completely and `>><` becomes `>`.
+/// With saturating arithmetic, `+-` is not a no-op at the bounds of a cell, so only pointer moves cancel.
+pub(super) fn cancel_opposites(
+    op_codes: impl Iterator<Item = (OpCode, SourceSpan)>,
+    options: &ParseOptions,
+) -> (Vec<OpCode>, Vec<SourceSpan>) {
+    let mut kept_op_codes: Vec<OpCode> = Vec::new();
+    let mut kept_spans = Vec::new();
+
+    for (code, span) in op_codes {
+        let cancels = match code {
+            OpCode::IncrementValue | OpCode::DecrementValue => options.arithmetic == CellArithmetic::Wrapping,
+            _ => code.opposite().is_some(),
+        };
+        if cancels && kept_op_codes.last().cloned() == code.opposite() {
+            kept_op_codes.pop();
+            kept_spans.pop();
+        } else {
+            kept_op_codes.push(code);
+            kept_spans.push(span);
+        }
+    }
+
+    (kept_op_codes, kept_spans)
+}
+
+/// The source range covering all of `instructions`
+fn covering(instructions: &[Spanned]) -> Range<usize> {
+    instructions[0].1.start..instructions[instructions.len() - 1].1.end
+}
+
+/// Replaces the zeroing idioms `[-]` and `[+]` with a single `SetZero`.
+/// `[+]` only terminates with wrapping arithmetic.
+fn optimize_clear_loops(instructions: &[Spanned], wrapping: bool) -> Vec<Spanned> {
+    let mut optimized = Vec::with_capacity(instructions.len());
+
+    let mut i = 0;
+    while i < instructions.len() {
+        match instructions[i..] {
+            [(Instruction::LoopHead(_), _), (Instruction::IncrementValue(1) | Instruction::DecrementValue(1), _), (Instruction::LoopEnd(_), _), ..]
+                if wrapping || instructions[i + 1].0 == Instruction::DecrementValue(1) =>
+            {
+                optimized.push((Instruction::SetZero, covering(&instructions[i..i + 3])));
+                i += 3;
+            }
+            _ => {
+                optimized.push(instructions[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    optimized
+}
+
+/// Folds a `SetZero` and the value change directly following it into a single `SetValue`, so `[-]+++` becomes `SetValue(3)`.
+/// Negative values need wrapping arithmetic.
+fn optimize_set_values(instructions: &[Spanned], wrapping: bool) -> Vec<Spanned> {
+    let mut optimized = Vec::with_capacity(instructions.len());
+
+    let mut i = 0;
+    while i < instructions.len() {
+        match instructions[i..] {
+            [(Instruction::SetZero, _), (Instruction::IncrementValue(n), _), ..] => {
+                optimized.push((Instruction::SetValue(n as isize), covering(&instructions[i..i + 2])));
+                i += 2;
+            }
+            [(Instruction::SetZero, _), (Instruction::DecrementValue(n), _), ..] if wrapping => {
+                optimized.push((Instruction::SetValue(-(n as isize)), covering(&instructions[i..i + 2])));
+                i += 2;
+            }
+            _ => {
+                optimized.push(instructions[i].clone());
+                i += 1;
+            }
+        }
+    }
+
+    optimized
+}
+
+/// Merges `SetZero`s on adjacent cells, like `[-]>[-]>[-]` for zeroing a buffer, into a single `FillZero`.
+/// Only single steps to the right qualify, so the zeroed cells are contiguous and the pointer ends on the last one like before.
+fn optimize_fill_zero(instructions: &[Spanned]) -> Vec<Spanned> {
+    let mut optimized = Vec::with_capacity(instructions.len());
+
+    let mut i = 0;
+    while i < instructions.len() {
+        if instructions[i].0 == Instruction::SetZero {
+            let mut count = 1;
+            while let [(Instruction::IncrementPointer(1), _), (Instruction::SetZero, _), ..] = instructions[i + 2 * count - 1..] {
+                count += 1;
+            }
+            if count > 1 {
+                let end = i + 2 * count - 1;
+                optimized.push((Instruction::FillZero { count }, covering(&instructions[i..end])));
+                i = end;
+                continue;
+            }
+        }
+
+        optimized.push(instructions[i].clone());
+        i += 1;
+    }
+
+    optimized
+}
+
+/// Lowers innermost loops that only move the pointer and change values to a `MultiplyAdd`, or a `CopyTo` if every factor is 1.
+/// A loop qualifies if the pointer ends up where it started and the current cell is decremented by exactly 1 per iteration,
+/// so the body runs `*ptr` times and every other touched cell receives `*ptr` times its per-iteration change.
+fn optimize_multiply_loops(instructions: &[Spanned]) -> Vec<Spanned> {
+    let mut optimized = Vec::with_capacity(instructions.len());
+
+    let mut i = 0;
+    while i < instructions.len() {
+        if let Instruction::LoopHead(_) = instructions[i].0 {
+            let body_length = instructions[i + 1..]
+                .iter()
+                .position(|(instruction, _)| !is_multiply_loop_body(instruction));
+
+            if let Some(body_length) = body_length {
+                let end = i + 1 + body_length;
+                if let Instruction::LoopEnd(_) = instructions[end].0 {
+                    if let Some(targets) = multiply_targets(&instructions[i + 1..end]) {
+                        let instruction = match copy_targets(&targets) {
+                            Some(targets) => Instruction::CopyTo { targets },
+                            None => Instruction::MultiplyAdd(targets),
+                        };
+                        optimized.push((instruction, covering(&instructions[i..=end])));
+                        i = end + 1;
+                        continue;
+                    }
+                }
+            }
+        }
+
+        optimized.push(instructions[i].clone());
+        i += 1;
+    }
+
+    optimized
+}
+
+fn is_multiply_loop_body(instruction: &Instruction<Unbound>) -> bool {
+    matches!(
+        instruction,
+        Instruction::IncrementPointer(_)
+            | Instruction::DecrementPointer(_)
+            | Instruction::IncrementValue(_)
+            | Instruction::DecrementValue(_)
+    )
+}
+
+/// Returns the `(offset, factor)` pairs of a multiply loop body or `None` if the body is not a multiply loop.
+fn multiply_targets(body: &[Spanned]) -> Option<Vec<(isize, isize)>> {
+    let mut offset: isize = 0;
+    let mut deltas = Vec::<(isize, isize)>::new();
+
+    for (instruction, _) in body {
+        let delta = match *instruction {
+            Instruction::IncrementPointer(n) => {
+                offset += n as isize;
+                continue;
+            }
+            Instruction::DecrementPointer(n) => {
+                offset -= n as isize;
+                continue;
+            }
+            Instruction::IncrementValue(n) => n as isize,
+            Instruction::DecrementValue(n) => -(n as isize),
+            _ => return None,
+        };
+
+        match deltas.iter_mut().find(|(o, _)| *o == offset) {
+            Some((_, factor)) => *factor += delta,
+            None => deltas.push((offset, delta)),
+        }
+    }
+
+    if offset != 0 {
+        return None;
+    }
+
+    match deltas.iter().position(|&(o, _)| o == 0) {
+        Some(index) if deltas[index].1 == -1 => {
+            deltas.remove(index);
+        }
+        _ => return None,
+    }
+
+    deltas.retain(|&(_, factor)| factor != 0);
+    deltas.sort_unstable();
+    Some(deltas)
+}
+
+/// Returns the offsets of multiply targets that all have a factor of 1, or `None` if a factor differs or the offsets are not
+/// distinct and other than 0. `multiply_targets` never returns such offsets, checking them keeps `CopyTo` well-defined regardless.
+fn copy_targets(targets: &[(isize, isize)]) -> Option<Vec<isize>> {
+    if targets.iter().any(|&(offset, factor)| factor != 1 || offset == 0) {
+        return None;
+    }
+    let offsets: Vec<isize> = targets.iter().map(|&(offset, _)| offset).collect();
+    // The targets are sorted, so equal offsets are adjacent
+    if offsets.windows(2).any(|pair| pair[0] == pair[1]) {
+        return None;
+    }
+    Some(offsets)
+}
+
+/// Replaces loops that only move the pointer, like `[>]` or `[<<<]`, by `ScanRight` and `ScanLeft` with the net movement of the body as the stride.
+/// A body of several moves, like `>>` and `<` that were not merged, only checks the cell it ends on, the cells in between are skipped.
+/// Loops whose moves cancel out never end once entered and are kept.
+fn optimize_scan_loops(instructions: &[Spanned]) -> Vec<Spanned> {
+    let mut optimized = Vec::with_capacity(instructions.len());
+
+    let mut i = 0;
+    while i < instructions.len() {
+        if let Instruction::LoopHead(_) = instructions[i].0 {
+            let moves = instructions[i + 1..]
+                .iter()
+                .take_while(|(instruction, _)| matches!(instruction, Instruction::IncrementPointer(_) | Instruction::DecrementPointer(_)))
+                .count();
+            let end = i + 1 + moves;
+            if moves > 0 && matches!(instructions.get(end), Some((Instruction::LoopEnd(_), _))) {
+                let stride: isize = instructions[i + 1..end]
+                    .iter()
+                    .map(|(instruction, _)| match *instruction {
+                        Instruction::IncrementPointer(n) => n as isize,
+                        Instruction::DecrementPointer(n) => -(n as isize),
+                        _ => unreachable!(),
+                    })
+                    .sum();
+                let scan = match stride.signum() {
+                    1 => Some(Instruction::ScanRight(stride as usize)),
+                    -1 => Some(Instruction::ScanLeft(stride.unsigned_abs())),
+                    _ => None,
+                };
+                if let Some(scan) = scan {
+                    optimized.push((scan, covering(&instructions[i..=end])));
+                    i = end + 1;
+                    continue;
+                }
+            }
+        }
+
+        optimized.push(instructions[i].clone());
+        i += 1;
+    }
+
+    optimized
+}
+
+/// Most iterations `unroll_counted_loops` unrolls a loop for
+const MAX_UNROLLED_TRIPS: isize = 8;
+/// Most instructions `unroll_counted_loops` replaces a loop with
+const MAX_UNROLLED_LENGTH: usize = 64;
+
+/// Unrolls loops that directly follow a `SetValue` and decrement the cell they test by exactly 1 per iteration, so they run a small
+/// number of times that is known in advance. `[-]+++[>.<-]` becomes `SetValue(3)` and the body three times, without the brackets.
+/// Loops with more than `MAX_UNROLLED_TRIPS` iterations or that would grow past `MAX_UNROLLED_LENGTH` instructions are kept.
+fn unroll_counted_loops(instructions: &[Spanned]) -> Vec<Spanned> {
+    let mut optimized = Vec::with_capacity(instructions.len());
+
+    let mut i = 0;
+    while i < instructions.len() {
+        if let Instruction::SetValue(trips) = instructions[i].0 {
+            if let Some(body) = counted_loop_body(&instructions[i + 1..]) {
+                if (1..=MAX_UNROLLED_TRIPS).contains(&trips) && body.len() * trips as usize <= MAX_UNROLLED_LENGTH {
+                    optimized.push(instructions[i].clone());
+                    for _ in 0..trips {
+                        optimized.extend_from_slice(body);
+                    }
+                    // The `SetValue`, both brackets and the body
+                    i += body.len() + 3;
+                    continue;
+                }
+            }
+        }
+
+        optimized.push(instructions[i].clone());
+        i += 1;
+    }
+
+    optimized
+}
+
+/// Merges adjacent instructions that `bind` would have run-length encoded and `cancel_opposites` cancelled if they had been
+/// adjacent in the source, like the ends of the copies `unroll_counted_loops` puts next to each other. A value change after a
+/// `SetZero` or `SetValue` is stored with it, so `[-]++[-.-.+]` becomes `SetValue(1), PutChar(1), DecrementValue(1), PutChar(2), ...`.
+/// That keeps the instructions the same as those of `Program::to_canonical_source` parsed again. Value changes are folded modulo
+/// `cell_size` like in `bind`.
+fn merge_runs(instructions: &[Spanned], cell_size: Option<usize>) -> Vec<Spanned> {
+    let mut merged: Vec<Spanned> = Vec::with_capacity(instructions.len());
+
+    for (instruction, span) in instructions {
+        let combined = merged.last().and_then(|(previous, _)| combine(previous, instruction, cell_size));
+        match combined {
+            Some(combined) => {
+                let (_, previous_span) = merged.pop().unwrap();
+                if let Some(combined) = combined {
+                    merged.push((combined, previous_span.start..span.end));
+                }
+            }
+            None => merged.push((instruction.clone(), span.clone())),
+        }
+    }
+
+    merged
+}
+
+/// The instruction that `first` followed by `second` amounts to, `Some(None)` if they cancel out or `None` if they do not combine
+fn combine(
+    first: &Instruction<Unbound>,
+    second: &Instruction<Unbound>,
+    cell_size: Option<usize>,
+) -> Option<Option<Instruction<Unbound>>> {
+    let fold = |amount: isize| cell_size.map_or(amount, |cell_size| amount % cell_size as isize);
+    let combined = match (first, second) {
+        (&Instruction::PutChar(m), &Instruction::PutChar(n)) => Instruction::PutChar(m + n),
+        (&Instruction::GetChar(m), &Instruction::GetChar(n)) => Instruction::GetChar(m + n),
+        (&Instruction::SetZero | &Instruction::SetValue(_), _) => {
+            let value = match *first {
+                Instruction::SetValue(value) => value,
+                _ => 0,
+            };
+            match fold(value + value_change(second)?) {
+                0 => Instruction::SetZero,
+                value => Instruction::SetValue(value),
+            }
+        }
+        _ => {
+            if let (Some(a), Some(b)) = (value_change(first), value_change(second)) {
+                match fold(a + b) {
+                    0 => return Some(None),
+                    amount if amount > 0 => Instruction::IncrementValue(amount as usize),
+                    amount => Instruction::DecrementValue(-amount as usize),
+                }
+            } else if let (Some(a), Some(b)) = (pointer_move(first), pointer_move(second)) {
+                match a + b {
+                    0 => return Some(None),
+                    offset if offset > 0 => Instruction::IncrementPointer(offset as usize),
+                    offset => Instruction::DecrementPointer(-offset as usize),
+                }
+            } else {
+                return None;
+            }
+        }
+    };
+    Some(Some(combined))
+}
+
+fn value_change(instruction: &Instruction<Unbound>) -> Option<isize> {
+    match *instruction {
+        Instruction::IncrementValue(n) => Some(n as isize),
+        Instruction::DecrementValue(n) => Some(-(n as isize)),
+        _ => None,
+    }
+}
+
+fn pointer_move(instruction: &Instruction<Unbound>) -> Option<isize> {
+    match *instruction {
+        Instruction::IncrementPointer(n) => Some(n as isize),
+        Instruction::DecrementPointer(n) => Some(-(n as isize)),
+        _ => None,
+    }
+}
+
+/// Returns the body of the innermost loop at the start of `instructions` if it leaves the pointer where it was, decrements the
+/// cell it tests by exactly 1 and changes no other cell, or `None` otherwise. Reading cells, like with `.`, is fine.
+/// A change of another cell could change the tested one too if both are the same cell of a short `TapeMode::Wrap` tape or
+/// address space, which the unrolled loop would not notice.
+fn counted_loop_body(instructions: &[Spanned]) -> Option<&[Spanned]> {
+    if !matches!(instructions.first(), Some((Instruction::LoopHead(_), _))) {
+        return None;
+    }
+
+    let mut offset: isize = 0;
+    let mut counter_delta: isize = 0;
+    for (index, (instruction, _)) in instructions.iter().enumerate().skip(1) {
+        match *instruction {
+            Instruction::LoopEnd(_) if offset == 0 && counter_delta == -1 => return Some(&instructions[1..index]),
+            Instruction::IncrementPointer(n) => offset += n as isize,
+            Instruction::DecrementPointer(n) => offset -= n as isize,
+            Instruction::IncrementValue(n) if offset == 0 => counter_delta += n as isize,
+            Instruction::DecrementValue(n) if offset == 0 => counter_delta -= n as isize,
+            Instruction::PutChar(_) => {}
+            // Nested loops, scans, stores and changes of other cells
+            _ => return None,
+        }
+    }
+    None
+}
+
+/// Defers pointer moves so value changes in between can be applied relative to the pointer,
+/// e.g. `>+++<<-` becomes `AddAtOffset { offset: 1, amount: 3 }, AddAtOffset { offset: -1, amount: -1 }, DecrementPointer(1)`.
+/// The accumulated movement is applied once before any instruction that needs the actual pointer position.
+/// Because of that, a pointer that only temporarily leaves the tape without touching a cell there is not an error anymore.
+fn optimize_offsets(instructions: &[Spanned]) -> Vec<Spanned> {
+    let mut optimized = Vec::with_capacity(instructions.len());
+
+    let mut offset: isize = 0;
+    // Source range of the moves that make up `offset`
+    let mut moves: Option<Range<usize>> = None;
+    for (instruction, span) in instructions {
+        let span = span.clone();
+        match *instruction {
+            Instruction::IncrementPointer(n) => {
+                offset += n as isize;
+                moves = Some(moves.map_or(span.clone(), |moves| moves.start..span.end));
+            }
+            Instruction::DecrementPointer(n) => {
+                offset -= n as isize;
+                moves = Some(moves.map_or(span.clone(), |moves| moves.start..span.end));
+            }
+            Instruction::IncrementValue(n) if offset != 0 => optimized.push((
+                Instruction::AddAtOffset {
+                    offset,
+                    amount: n as isize,
+                },
+                span,
+            )),
+            Instruction::DecrementValue(n) if offset != 0 => optimized.push((
+                Instruction::AddAtOffset {
+                    offset,
+                    amount: -(n as isize),
+                },
+                span,
+            )),
+            _ => {
+                push_pointer_move(&mut optimized, offset, moves.take());
+                offset = 0;
+                optimized.push((instruction.clone(), span));
+            }
+        }
+    }
+    push_pointer_move(&mut optimized, offset, moves);
+
+    optimized
+}
+
+fn push_pointer_move(instructions: &mut Vec<Spanned>, offset: isize, span: Option<Range<usize>>) {
+    let instruction = match offset {
+        0 => return,
+        o if o > 0 => Instruction::IncrementPointer(o as usize),
+        o => Instruction::DecrementPointer(-o as usize),
+    };
+    instructions.push((instruction, span.unwrap()));
+}
+
+/// Evaluates the straight-line start of a program, up to the first loop, I/O or breakpoint, at compile time.
+/// It is replaced by a single change of every touched cell and a single move to the final pointer position, so `+++>++<+>+`
+/// becomes `IncrementValue(4), AddAtOffset { offset: 1, amount: 3 }, IncrementPointer(1)`. A cell that is stored to, like with
+/// `[-]+++`, is set to its final value instead, `SetZero` included.
+/// Nothing is assumed about the tape, so the result is the same on a tape left by the caller. Changes commute even if two offsets
+/// are the same cell of a short `TapeMode::Wrap` tape, but a store does not, so the prefix ends at a store if another cell was
+/// touched before.
+/// Like `optimize_offsets`, cells that end up unchanged are not touched anymore, so accessing them past the end is not an error anymore.
+fn propagate_constants(instructions: &[Spanned]) -> Vec<Spanned> {
+    let mut pointer: isize = 0;
+    // The changes of the touched cells, in the order they were first touched
+    let mut cells = Vec::<(isize, isize)>::new();
+    // The cell that was stored to, its entry in `cells` is the value it ends up with
+    let mut stored = None;
+    let mut prefix_length = 0;
+
+    for (instruction, _) in instructions {
+        match *instruction {
+            Instruction::IncrementPointer(n) => pointer += n as isize,
+            Instruction::DecrementPointer(n) => pointer -= n as isize,
+            Instruction::IncrementValue(n) => *cell_value(&mut cells, pointer) += n as isize,
+            Instruction::DecrementValue(n) => *cell_value(&mut cells, pointer) -= n as isize,
+            Instruction::AddAtOffset { offset, amount } => *cell_value(&mut cells, pointer + offset) += amount,
+            Instruction::SetZero | Instruction::SetValue(_) if cells.iter().all(|&(offset, _)| offset == pointer) => {
+                *cell_value(&mut cells, pointer) = match *instruction {
+                    Instruction::SetValue(value) => value,
+                    _ => 0,
+                };
+                stored = Some(pointer);
+            }
+            _ => break,
+        }
+        prefix_length += 1;
+    }
+    if prefix_length == 0 {
+        return instructions.to_vec();
+    }
+
+    let span = covering(&instructions[..prefix_length]);
+    let mut evaluated: Vec<Spanned> = Vec::new();
+    // The stored cell is set first, so the changes of the others cannot be overwritten if they are the same cell
+    let origin = stored.unwrap_or(0);
+    if let Some(&(offset, value)) = cells.iter().find(|&&(offset, _)| Some(offset) == stored) {
+        push_pointer_move(&mut evaluated, offset, Some(span.clone()));
+        let store = match value {
+            0 => Instruction::SetZero,
+            value => Instruction::SetValue(value),
+        };
+        evaluated.push((store, span.clone()));
+    }
+    for &(offset, amount) in &cells {
+        if Some(offset) == stored || amount == 0 {
+            continue;
+        }
+        let change = match offset - origin {
+            0 if amount > 0 => Instruction::IncrementValue(amount as usize),
+            0 => Instruction::DecrementValue(-amount as usize),
+            offset => Instruction::AddAtOffset { offset, amount },
+        };
+        evaluated.push((change, span.clone()));
+    }
+    push_pointer_move(&mut evaluated, pointer - origin, Some(span));
+
+    if evaluated.len() >= prefix_length {
+        return instructions.to_vec();
+    }
+    evaluated.extend_from_slice(&instructions[prefix_length..]);
+    evaluated
+}
+
+/// The value of the cell at `offset` in `(offset, value)` pairs, adding the cell with a zero if it is not there yet
+fn cell_value(cells: &mut Vec<(isize, isize)>, offset: isize) -> &mut isize {
+    let index = match cells.iter().position(|&(o, _)| o == offset) {
+        Some(index) => index,
+        None => {
+            cells.push((offset, 0));
+            cells.len() - 1
+        }
+    };
+    &mut cells[index].1
+}
+
+/// Removes loops that can never be entered because the current cell is known to be zero when they are reached.
+/// That is the case for loops at the very start of the program (if it starts on a fresh tape), like the common `[ comment ]` header,
+/// and for loops directly following another loop, including loops already lowered to `SetZero`, `MultiplyAdd`, `CopyTo` or a scan.
+fn remove_dead_loops(instructions: &[Spanned], starts_on_zero: bool) -> Vec<Spanned> {
+    let mut optimized = Vec::with_capacity(instructions.len());
+
+    let mut cell_is_zero = starts_on_zero;
+    let mut i = 0;
+    while i < instructions.len() {
+        match instructions[i].0 {
+            Instruction::LoopHead(_) if cell_is_zero => {
+                i += dead_loop_length(&instructions[i..]);
+                continue;
+            }
+            Instruction::LoopEnd(_)
+            | Instruction::SetZero
+            | Instruction::FillZero { .. }
+            | Instruction::MultiplyAdd(_)
+            | Instruction::CopyTo { .. }
+            | Instruction::ScanRight(_)
+            | Instruction::ScanLeft(_) => cell_is_zero = true,
+            _ => cell_is_zero = false,
+        }
+
+        optimized.push(instructions[i].clone());
+        i += 1;
+    }
+
+    optimized
+}
+
+/// The number of instructions from the `LoopHead` at the start of `instructions` up to and including its `LoopEnd`
+fn dead_loop_length(instructions: &[Spanned]) -> usize {
+    let mut depth = 0;
+    for (index, (instruction, _)) in instructions.iter().enumerate() {
+        match instruction {
+            Instruction::LoopHead(_) => depth += 1,
+            Instruction::LoopEnd(_) => {
+                depth -= 1;
+                if depth == 0 {
+                    return index + 1;
+                }
+            }
+            _ => (),
+        }
+    }
+    unreachable!("Brackets are checked to be balanced before optimizing")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::brainfuck::testing::assert_same_as_unoptimized;
+    use crate::brainfuck::{CellArithmetic, ExecutionOptions, Instruction, OpCode, ParseOptions, Program, SourceSpan};
+
+    #[test]
+    fn clear_loops_become_set_zero() {
+        let program = Program::parse(",[-],[+]").unwrap();
+        assert_eq!(
+            program.instructions(),
+            [Instruction::GetChar(1), Instruction::SetZero, Instruction::GetChar(1), Instruction::SetZero]
+        );
+        assert!(program.instructions().len() < program.op_code_count());
+        assert_same_as_unoptimized(",[-].>+++[-].,[+].", &ExecutionOptions::default(), b"ab");
+    }
+
+    #[test]
+    fn clear_loops_counting_up_are_kept_when_saturating() {
+        let saturating = ParseOptions {
+            arithmetic: CellArithmetic::Saturating,
+            ..ParseOptions::default()
+        };
+        let program = Program::parse_with_options(",[-],[+]", &saturating).unwrap();
+        assert_eq!(program.instructions()[1], Instruction::SetZero);
+        assert_eq!(program.instructions()[3], Instruction::LoopHead(5));
+    }
+
+    #[test]
+    fn clear_loops_followed_by_a_change_become_set_value() {
+        assert_eq!(Program::parse(",[-]+++").unwrap().instructions(), [Instruction::GetChar(1), Instruction::SetValue(3)]);
+        assert_eq!(Program::parse(",[-]--").unwrap().instructions(), [Instruction::GetChar(1), Instruction::SetValue(-2)]);
+        assert_same_as_unoptimized(",[-]+++.,[-]--.", &ExecutionOptions::default(), b"ab");
+
+        let saturating = ParseOptions {
+            arithmetic: CellArithmetic::Saturating,
+            ..ParseOptions::default()
+        };
+        assert_eq!(
+            Program::parse_with_options(",[-]--", &saturating).unwrap().instructions(),
+            [Instruction::GetChar(1), Instruction::SetZero, Instruction::DecrementValue(2)]
+        );
+    }
+
+    #[test]
+    fn changes_next_to_the_pointer_become_offsets() {
+        assert_eq!(
+            Program::parse(",>+++<<-.").unwrap().instructions(),
+            [
+                Instruction::GetChar(1),
+                Instruction::AddAtOffset { offset: 1, amount: 3 },
+                Instruction::AddAtOffset { offset: -1, amount: -1 },
+                Instruction::DecrementPointer(1),
+                Instruction::PutChar(1),
+            ]
+        );
+        for code in [">,>+++<<-.>.>.", ">,[>+>++<<.-]>.>.", ">>,[<+>>-<-]<<.>>>.", ">>++++[<+++>-<<++>>-]<.<.>>."] {
+            assert_same_as_unoptimized(code, &ExecutionOptions::default(), b"\x05");
+        }
+    }
+
+    #[test]
+    fn opposite_commands_cancel_out() {
+        let program = Program::parse("+-<>-+><++>><<--").unwrap();
+        assert_eq!(program.instructions(), []);
+        assert_eq!(program.bench_run(&mut [0u8; 1], &ExecutionOptions::default()).unwrap().steps, 0);
+
+        assert_eq!(
+            Program::parse(",>><.").unwrap().instructions(),
+            [Instruction::GetChar(1), Instruction::IncrementPointer(1), Instruction::PutChar(1)]
+        );
+    }
+
+    #[test]
+    fn cancelling_twice_changes_nothing() {
+        let options = ParseOptions::default();
+        let span = SourceSpan {
+            line: 1,
+            column: 1,
+            byte_offset: 0,
+        };
+        let cancel = |op_codes: Vec<OpCode>| super::cancel_opposites(op_codes.into_iter().map(|code| (code, span)), &options).0;
+
+        let once = cancel(Program::op_codes("+>-<<>+-[->+<]>><", &options));
+        assert_eq!(once, Program::op_codes("+>-<[->+<]>", &options));
+        assert_eq!(cancel(once.clone()), once);
+    }
+
+    #[test]
+    fn leading_constants_are_evaluated_at_compile_time() {
+        let code = format!("{}>{}>{}>{}<<<.>.>..>.", "+".repeat(72), "+".repeat(101), "+".repeat(108), "+".repeat(111));
+        let program = Program::parse(&code).unwrap();
+        assert_eq!(
+            program.instructions()[..4],
+            [
+                Instruction::IncrementValue(72),
+                Instruction::AddAtOffset { offset: 1, amount: 101 },
+                Instruction::AddAtOffset { offset: 2, amount: 108 },
+                Instruction::AddAtOffset { offset: 3, amount: 111 },
+            ]
+        );
+        assert_eq!(program.run_to_string(&mut [0u8; 4], b"").unwrap(), b"Hello");
+        assert_same_as_unoptimized(&code, &ExecutionOptions::default(), b"");
+
+        assert_eq!(
+            Program::parse("+++>++<+>+").unwrap().instructions(),
+            [
+                Instruction::IncrementValue(4),
+                Instruction::AddAtOffset { offset: 1, amount: 3 },
+                Instruction::IncrementPointer(1),
+            ]
+        );
+        assert_eq!(
+            Program::parse("[-]+++>>+>+<<<++").unwrap().instructions(),
+            [
+                Instruction::SetValue(5),
+                Instruction::AddAtOffset { offset: 2, amount: 1 },
+                Instruction::AddAtOffset { offset: 3, amount: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn header_comment_loops_are_removed() {
+        let code = "[ Prints A. It reads no input, and the + and - in here never run ]++++++++[>++++++++<-]>+.";
+        let program = Program::parse(code).unwrap();
+        assert!(!program.instructions().iter().any(|instruction| matches!(instruction, Instruction::LoopHead(_))));
+        assert_eq!(program.run_to_string(&mut [0u8; 2], b"").unwrap(), b"A");
+
+        let fragment = ParseOptions {
+            fragment: true,
+            ..ParseOptions::default()
+        };
+        let program = Program::parse_with_options("[.-]", &fragment).unwrap();
+        assert_eq!(program.instructions()[0], Instruction::LoopHead(3));
+    }
+
+    #[test]
+    fn loops_directly_after_loops_are_removed() {
+        assert_eq!(
+            Program::parse(",[.-][.][,]").unwrap().instructions(),
+            [
+                Instruction::GetChar(1),
+                Instruction::LoopHead(4),
+                Instruction::PutChar(1),
+                Instruction::DecrementValue(1),
+                Instruction::LoopEnd(1),
+            ]
+        );
+        assert_eq!(Program::parse(",[-][.]").unwrap().instructions(), [Instruction::GetChar(1), Instruction::SetZero]);
+    }
+
+    #[test]
+    fn counted_loops_are_unrolled() {
+        let is_unrolled = |code: &str| {
+            let program = Program::parse(code).unwrap();
+            !program.instructions().iter().any(|instruction| matches!(instruction, Instruction::LoopHead(_)))
+        };
+        let run = |code: &str, input: &[u8]| {
+            let mut memory = [0u8; 8];
+            let output = Program::parse(code).unwrap().run_to_string(&mut memory, input).unwrap();
+            (output, memory)
+        };
+        let loops = [
+            ("+++", ".>.<-", true),
+            // 8 trips of 5 instructions, then one trip too many
+            ("++++++++", ".>.<-", true),
+            ("+++++++++", ".>.<-", false),
+            // 8 trips of 8 instructions, then 5 trips of 13 to grow one instruction past the limit
+            ("++++++++", ">.>.>.<<<-", true),
+            ("+++++", ".>.>.>.>.>.<<<<<-", false),
+            // Changing another cell could change the counter too
+            ("+++", ">+.<-", false),
+            ("+++", ">,<-", false),
+        ];
+        for (trips, body, unrolled) in loops {
+            let code = format!(">+++<,[-]{}[{}]", trips, body);
+            assert_eq!(is_unrolled(&code), unrolled, "{:?}", code);
+            // The trip count is not known in advance if it is read instead, so the loop is kept
+            assert_eq!(run(&code, b"abcd"), run(&format!(">+++<,[{}]", body), &[trips.len() as u8, b'b', b'c', b'd']), "{:?}", code);
+        }
+    }
+
+    #[test]
+    fn copies_of_an_unrolled_body_are_merged() {
+        let program = Program::parse("[-]++[-.-.+]").unwrap();
+        assert_eq!(
+            program.instructions(),
+            [
+                Instruction::SetValue(1),
+                Instruction::PutChar(1),
+                Instruction::DecrementValue(1),
+                Instruction::PutChar(2),
+                Instruction::DecrementValue(1),
+                Instruction::PutChar(1),
+                Instruction::IncrementValue(1),
+            ]
+        );
+        assert_eq!(Program::parse(&program.to_canonical_source()).unwrap().instructions(), program.instructions());
+        assert_same_as_unoptimized("[-]++[-.-.+]", &ExecutionOptions::default(), b"");
+
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::{self, Read, Write};
+
+use super::{Cell, ExecutionOptions, Halt, Program, RuntimeError};
+
+/// A tape and a data pointer that persist across programs, so every program continues on the cells and at the position the
+/// previous one left. Parse the programs with `ParseOptions::fragment`, otherwise they are optimized for a fresh tape.
+pub struct Session<C: Cell = u8> {
+    memory: Vec<C>,
+    data_pointer: usize,
+    options: ExecutionOptions,
+}
+
+impl<C: Cell> Session<C> {
+    /// Starts on cell 0 of `capacity` zeroed cells
+    pub fn new(capacity: usize) -> Session<C> {
+        Self::with_options(capacity, ExecutionOptions::default())
+    }
+
+    pub fn with_options(capacity: usize, options: ExecutionOptions) -> Session<C> {
+        Session {
+            memory: vec![C::default(); capacity],
+            data_pointer: 0,
+            options,
+        }
+    }
+
+    pub fn memory(&self) -> &[C] {
+        &self.memory
+    }
+
+    pub fn data_pointer(&self) -> usize {
+        self.data_pointer
+    }
+
+    /// Runs `program` on stdin/stdout, starting where the previous program stopped
+    pub fn run_program(&mut self, program: &Program) -> Result<Halt, RuntimeError> {
+        let stdin = io::stdin();
+        let stdout = io::stdout();
+        self.run_program_io(program, stdin.lock(), stdout.lock())
+    }
+
+    /// Same as `run_program`, but reads from `input` and writes to `output`.
+    /// If the program fails, the tape and the data pointer stay as they were when it stopped, so later programs can still run.
+    pub fn run_program_io<R: Read, W: Write>(&mut self, program: &Program, input: R, output: W) -> Result<Halt, RuntimeError> {
+        program.run_io_at(&mut self.memory, &mut self.data_pointer, input, output, &self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::Session;
+    use crate::brainfuck::{Halt, ParseOptions, Program, RuntimeError};
+
+    fn fragment(code: &str) -> Program {
+        let options = ParseOptions {
+            fragment: true,
+            ..ParseOptions::default()
+        };
+        Program::parse_with_options(code, &options).unwrap()
+    }
+
+    #[test]
+    fn programs_continue_where_the_previous_one_stopped() {
+        let mut session = Session::<u8>::new(4);
+        let mut output = Vec::new();
+        // The input arrives in two chunks, one for each program
+        assert_eq!(session.run_program_io(&fragment(",>,"), &b"ab"[..], &mut output).unwrap(), Halt::Finished);
+        assert_eq!((session.data_pointer(), session.memory()), (1, &b"ab\0\0"[..]));
+        // Without `fragment`, the loop would be dropped because the program seems to start on a zero cell
+        assert_eq!(session.run_program_io(&fragment("[.>],<<."), &b"c"[..], &mut output).unwrap(), Halt::Finished);
+        assert_eq!((session.data_pointer(), session.memory()), (0, &b"abc\0"[..]));
+        assert_eq!(output, b"ba");
+    }
+
+    #[test]
+    fn failed_programs_leave_the_tape_where_they_stopped() {
+        let mut session = Session::<u8>::new(2);
+        let result = session.run_program_io(&fragment("+>++>+++"), io::empty(), io::sink());
+        assert!(matches!(result, Err(RuntimeError::PointerOverflow { position: 2 })), "{:?}", result);
+        // The moves are folded into offsets, so the pointer did not leave the first cell yet
+        assert_eq!((session.data_pointer(), session.memory()), (0, &[1, 2][..]));
+
+        session.run_program_io(&fragment("[->+<]"), io::empty(), io::sink()).unwrap();
+        assert_eq!((session.data_pointer(), session.memory()), (0, &[0, 3][..]));
+    }
+}
@@ -0,0 +1,248 @@
+use std::convert::TryFrom;
+
+use itertools::Itertools;
+
+use super::{Cell, Signedness};
+
+/// The cells an `Interpreter` works on. Only an owned tape can grow.
+pub(super) enum Tape<'a, C: Cell> {
+    Borrowed(&'a mut [C]),
+    /// Grows by doubling its length when a cell past the end is accessed
+    Growable(Vec<C>),
+}
+
+impl<'a, C: Cell> Tape<'a, C> {
+    pub(super) fn cells(&self) -> &[C] {
+        match self {
+            Tape::Borrowed(cells) => cells,
+            Tape::Growable(cells) => cells,
+        }
+    }
+
+    pub(super) fn cells_mut(&mut self) -> &mut [C] {
+        match self {
+            Tape::Borrowed(cells) => cells,
+            Tape::Growable(cells) => cells,
+        }
+    }
+
+    /// Makes sure `index` is on the tape, growing it if possible.
+    /// Returns false if `index` lies beyond the end of a tape that cannot grow.
+    pub(super) fn reserve(&mut self, index: usize) -> bool {
+        match self {
+            Tape::Borrowed(cells) => index < cells.len(),
+            Tape::Growable(cells) => {
+                if index >= cells.len() {
+                    let new_len = (cells.len() * 2).max(index + 1);
+                    cells.resize(new_len, C::default());
+                }
+                true
+            }
+        }
+    }
+
+    /// Adds at least `count` cells before the first one, returns how many were added.
+    /// Returns 0 if the tape cannot grow.
+    pub(super) fn grow_left(&mut self, count: usize) -> usize {
+        match self {
+            Tape::Borrowed(_) => 0,
+            Tape::Growable(cells) => {
+                // Doubling keeps moving the cells cheap when the program keeps walking left
+                let count = count.max(cells.len());
+                cells.splice(0..0, std::iter::repeat(C::default()).take(count));
+                count
+            }
+        }
+    }
+
+    /// Overwrites the cells with `cells`. A borrowed tape cannot change its length, so `cells` has to match it.
+    pub(super) fn restore(&mut self, cells: &[C]) {
+        match self {
+            Tape::Borrowed(tape) => {
+                assert_eq!(tape.len(), cells.len(), "Cannot restore a snapshot of a tape with a different length");
+                tape.copy_from_slice(cells);
+            }
+            Tape::Growable(tape) => {
+                tape.clear();
+                tape.extend_from_slice(cells);
+            }
+        }
+    }
+
+    pub(super) fn into_vec(self) -> Option<Vec<C>> {
+        match self {
+            Tape::Borrowed(_) => None,
+            Tape::Growable(cells) => Some(cells),
+        }
+    }
+}
+
+/// Renders the cells within `radius` of `data_pointer` as rows of indices, decimal values and ASCII characters, the current cell in brackets.
+/// The window is cut off at both ends of the tape.
+pub fn dump_tape<C: Cell>(memory: &[C], data_pointer: usize, radius: usize) -> String {
+    dump_tape_with_signedness(memory, data_pointer, radius, Signedness::Unsigned)
+}
+
+/// Same as `dump_tape`, but shows the values as two's complement numbers, like `-128..=127` for bytes, for `Signedness::Signed`.
+pub fn dump_tape_with_signedness<C: Cell>(memory: &[C], data_pointer: usize, radius: usize, signedness: Signedness) -> String {
+    let end = data_pointer.saturating_add(radius).saturating_add(1).min(memory.len());
+    let start = data_pointer.saturating_sub(radius).min(end);
+    // Wider cells can hold values longer than a byte
+    let value_width = memory[start..end].iter().map(|&cell| signedness.render(cell).len()).max().unwrap_or(0);
+    let width = end.to_string().len().max(value_width).max(match signedness {
+        Signedness::Unsigned => 3,
+        Signedness::Signed => 4,
+    });
+
+    let mut indices = String::new();
+    let mut values = String::new();
+    let mut characters = String::new();
+    for (index, &cell) in memory.iter().enumerate().take(end).skip(start) {
+        let (open, close) = if index == data_pointer { ('[', ']') } else { (' ', ' ') };
+        let character = match u8::try_from(cell.to_usize()) {
+            Ok(byte) if byte.is_ascii_graphic() || byte == b' ' => byte as char,
+            _ => '.',
+        };
+
+        indices.push_str(&format!("{}{:>width$}{}", open, index, close, width = width));
+        values.push_str(&format!("{}{:>width$}{}", open, signedness.render(cell), close, width = width));
+        characters.push_str(&format!("{}{:>width$}{}", open, character, close, width = width));
+    }
+
+    format!("{}\n{}\n{}\n", indices, values, characters)
+}
+
+/// The data pointer and the non-zero cells of a tape after a run, see `Program::run_to_state`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TapeState {
+    pub pointer: usize,
+    /// The index and value of every non-zero cell, ordered by index
+    pub cells: Vec<(usize, usize)>,
+}
+
+impl TapeState {
+    pub fn new<C: Cell>(memory: &[C], pointer: usize) -> TapeState {
+        let cells = memory
+            .iter()
+            .enumerate()
+            .filter(|(_, cell)| !cell.is_zero())
+            .map(|(index, cell)| (index, cell.to_usize()))
+            .collect();
+        TapeState { pointer, cells }
+    }
+
+    /// Renders the state as `{ "pointer": 3, "cells": {"0": 72, "1": 101} }`. JSON object keys have to be strings, so the indices are quoted.
+    pub fn to_json(&self) -> String {
+        let cells = self
+            .cells
+            .iter()
+            .map(|(index, value)| format!("\"{}\": {}", index, value))
+            .join(", ");
+        format!("{{ \"pointer\": {}, \"cells\": {{{}}} }}", self.pointer, cells)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+    use crate::brainfuck::{ExecutionOptions, Halt, Interpreter, Program, TapeMode};
+
+    #[test]
+    fn growable_tapes_grow_to_the_right() {
+        let mut tape = Tape::Growable(vec![0u8]);
+        assert!(tape.reserve(0));
+        assert_eq!(tape.cells().len(), 1);
+        // At least doubled, or up to the index if that is further away
+        assert!(tape.reserve(1));
+        assert_eq!(tape.cells().len(), 2);
+        assert!(tape.reserve(9));
+        assert_eq!(tape.cells().len(), 10);
+        assert!(tape.reserve(10));
+        assert_eq!(tape.cells().len(), 20);
+
+        let mut memory = [0u8; 2];
+        let mut borrowed = Tape::Borrowed(&mut memory);
+        assert!(borrowed.reserve(1));
+        assert!(!borrowed.reserve(2));
+        assert_eq!(borrowed.cells().len(), 2);
+    }
+
+    #[test]
+    fn growable_tapes_grow_to_the_left() {
+        let mut tape = Tape::Growable(vec![7u8, 8]);
+        assert_eq!(tape.grow_left(1), 2);
+        assert_eq!(tape.cells(), [0, 0, 7, 8]);
+        assert_eq!(tape.grow_left(6), 6);
+        assert_eq!(tape.cells(), [0, 0, 0, 0, 0, 0, 0, 0, 7, 8]);
+
+        let mut memory = [7u8];
+        assert_eq!(Tape::Borrowed(&mut memory).grow_left(1), 0);
+    }
+
+    #[test]
+    fn origin_follows_the_cells_added_to_the_left() {
+        let bidirectional = ExecutionOptions {
+            tape_mode: TapeMode::Bidirectional,
+            ..ExecutionOptions::default()
+        };
+        let program = Program::parse("+>++<<+++<<<<++++>>>>>>>>+<").unwrap();
+        let mut interpreter = Interpreter::<u8>::growable(&program, bidirectional);
+        let mut steps = 0;
+        while !interpreter.is_halted() {
+            interpreter.step().unwrap();
+            steps += 1;
+            // The cell the tape started with stays at the origin, whatever was added before it
+            assert_eq!(interpreter.memory()[interpreter.origin()], 1, "after {} steps", steps);
+        }
+        // Growing by 1 cell added 2 and growing by 3 added 4, doubling the tape each time
+        assert_eq!(interpreter.origin(), 6);
+        assert_eq!(interpreter.data_pointer(), 8);
+        assert_eq!(interpreter.memory(), [0, 4, 0, 0, 0, 3, 1, 2, 0, 1, 0, 0, 0, 0, 0, 0]);
+
+        // Other modes never add cells to the left
+        let program = Program::parse("+<").unwrap();
+        let mut interpreter = Interpreter::<u8>::growable(&program, ExecutionOptions::default());
+        interpreter.step().unwrap();
+        assert!(interpreter.step().is_err());
+        assert_eq!(interpreter.origin(), 0);
+    }
+
+    #[test]
+    fn programs_keep_the_cells_of_a_grown_tape() {
+        let program = Program::parse("+>>>+++>>>>>>>>+<.").unwrap();
+        let (halt, memory) = program.run_io_growable::<u8, _, _>(io::empty(), io::sink(), &ExecutionOptions::default()).unwrap();
+        assert_eq!(halt, Halt::Finished);
+        // From 1 cell to 4 for the cell at 3, then to 12 for the one at 11
+        assert_eq!(memory, [1, 0, 0, 3, 0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn dumps_are_cut_off_at_the_ends_of_the_tape() {
+        assert_eq!(dump_tape::<u8>(&[], 0, 2), "\n\n\n");
+        let memory = [72u8, 105, 33];
+        assert_eq!(dump_tape(&memory, 2, 1), "   1 [  2]\n 105 [ 33]\n   i [  !]\n");
+        assert_eq!(dump_tape(&memory, 0, 1), "[  0]   1 \n[ 72] 105 \n[  H]   i \n");
+        assert_eq!(dump_tape(&memory, 1, 0), "[  1]\n[105]\n[  i]\n");
+    }
+
+    #[test]
+    fn dumps_show_signed_values() {
+        let memory = [65u8, 200, 0];
+        assert_eq!(dump_tape(&memory, 1, 1), "   0 [  1]   2 \n  65 [200]   0 \n   A [  .]   . \n");
+        assert_eq!(
+            dump_tape_with_signedness(&memory, 1, 1, Signedness::Signed),
+            "    0 [   1]    2 \n   65 [ -56]    0 \n    A [   .]    . \n"
+        );
+        // The extremes of a byte, and wider cells widening the columns
+        assert_eq!(
+            dump_tape_with_signedness(&[128u8, 127, 255], 2, 2, Signedness::Signed),
+            "    0     1 [   2]\n -128   127 [  -1]\n    .     . [   .]\n"
+        );
+        assert_eq!(
+            dump_tape_with_signedness(&[40000u16, 1], 1, 1, Signedness::Signed),
+            "      0 [     1]\n -25536 [     1]\n      . [     .]\n"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+//! Fixtures and assertions shared by the tests of the modules.
+
+use super::{ExecutionOptions, Halt, ParseOptions, Program, TapeMode};
+
+/// Turns every command into its own instruction, as the reference for the optimized programs
+pub(super) fn unoptimized() -> ParseOptions {
+    ParseOptions {
+        unoptimized: true,
+        ..ParseOptions::default()
+    }
+}
+
+pub(super) fn wrap() -> ExecutionOptions {
+    ExecutionOptions {
+        tape_mode: TapeMode::Wrap,
+        ..ExecutionOptions::default()
+    }
+}
+
+/// Runs `code` with `input` on a tape of 16 cells and returns how it stopped, the output and the final tape
+pub(super) fn run_with_options(
+    code: &str,
+    parse_options: &ParseOptions,
+    options: &ExecutionOptions,
+    input: &[u8],
+) -> (Halt, Vec<u8>, Vec<u8>) {
+    let mut memory = vec![0u8; 16];
+    let mut output = Vec::new();
+    let program = Program::parse_with_options(code, parse_options).unwrap();
+    let halt = program.run_io_with_options(&mut memory, input, &mut output, options).unwrap();
+    (halt, output, memory)
+}
+
+/// Asserts that `code` behaves the same with the commands run one at a time, up to the limit it stops at
+pub(super) fn assert_same_as_unoptimized(code: &str, options: &ExecutionOptions, input: &[u8]) {
+    assert_eq!(
+        run_with_options(code, &ParseOptions::default(), options, input),
+        run_with_options(code, &unoptimized(), options, input),
+        "{:?} with {:?}",
+        code,
+        options
+    );
+}
@@ -0,0 +1,6 @@
+//! A brainfuck interpreter. Parse a program with `Program::parse` and execute it with one of the `Program::run` functions,
+//! or step through it with an `Interpreter`.
+
+mod brainfuck;
+
+pub use crate::brainfuck::*;
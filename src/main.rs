@@ -1,34 +1,899 @@
+use std::cmp::Reverse;
 use std::env;
+use std::fmt;
 use std::fs;
+use std::io::{self, BufRead, IsTerminal, Read, Write};
+use std::num::NonZeroUsize;
+use std::path::Path;
+use std::process;
+#[cfg(feature = "signals")]
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
-mod brainfuck;
+/// Environment variable with the memory size to use if none is given on the command line
+const MEMORY_SIZE_VARIABLE: &str = "BF_MEMORY_SIZE";
+
+enum Emit {
+    C,
+    Rust,
+    Llvm,
+    Dot,
+}
+
+struct Arguments {
+    /// Concatenated in this order, empty in the REPL
+    program_paths: Vec<String>,
+    memory_capacity: Option<usize>,
+    emit: Option<Emit>,
+    breakpoints: bool,
+    dump_tape: bool,
+    trace: bool,
+    color: ColorChoice,
+    saturating: bool,
+    input: Option<Input>,
+    dump_ir: bool,
+    profile: bool,
+    timeout: Option<Duration>,
+    normalize_newlines: bool,
+    decimal_output: bool,
+    seven_bit_output: bool,
+    signed: bool,
+    bench: bool,
+    address_space: Option<NonZeroUsize>,
+    max_output: Option<u64>,
+    warnings: bool,
+    final_newline: bool,
+    catch_interrupt: bool,
+}
+
+/// Whether `--trace` highlights its output with ANSI colors
+enum ColorChoice {
+    /// Only if stderr is a terminal and `NO_COLOR` is not set
+    Auto,
+    Always,
+    Never,
+}
+
+enum Trace {
+    Off,
+    Plain,
+    Colored,
+}
+
+/// Where `,` reads from instead of stdin
+enum Input {
+    Text(String),
+    File(String),
+    /// Everything after the first `!` in the program source
+    Embedded,
+}
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let program_line = args.first().map_or("./brainfuck", |a| a.as_str());
+    // A caller may not even pass the name of the program
+    let args = args.get(1..).unwrap_or_default();
 
-    match args.len() {
-        1 => help(args[0].as_str()),
-        2 => run(read_file(&args[1]).as_str(), None),
-        3 => run(
-            read_file(&args[1]).as_str(),
-            Some(args[2].as_str().parse::<usize>().unwrap()),
-        ),
-        _ => help("./brainfuck"),
+    if args.iter().any(|arg| arg == "--help" || arg == "-h") {
+        println!("{}", usage(program_line));
+        return;
+    }
+
+    let arguments = match parse_arguments(args) {
+        Some(arguments) => arguments,
+        None => help(program_line),
+    };
+
+    let parse_options = brainfuck::ParseOptions {
+        breakpoints: arguments.breakpoints,
+        dump_tape: arguments.dump_tape,
+        cell_bits: Some(u8::BITS),
+        arithmetic: if arguments.saturating {
+            brainfuck::CellArithmetic::Saturating
+        } else {
+            brainfuck::CellArithmetic::Wrapping
+        },
+        ..Default::default()
+    };
+    let memory_capacity = arguments
+        .memory_capacity
+        .or_else(memory_capacity_from_env)
+        .unwrap_or(brainfuck::DEFAULT_MEMORY_CAPACITY);
+    let execution_options = brainfuck::ExecutionOptions {
+        timeout: arguments.timeout,
+        address_space: arguments.address_space,
+        max_output: arguments.max_output,
+        output_encoding: if arguments.decimal_output {
+            brainfuck::OutputEncoding::Decimal
+        } else {
+            brainfuck::OutputEncoding::Byte
+        },
+        output_mask: if arguments.seven_bit_output {
+            brainfuck::OutputMask::SevenBit
+        } else {
+            brainfuck::OutputMask::Off
+        },
+        signedness: if arguments.signed {
+            brainfuck::Signedness::Signed
+        } else {
+            brainfuck::Signedness::Unsigned
+        },
+        ..Default::default()
+    };
+
+    if arguments.program_paths.is_empty() {
+        return repl(memory_capacity, &parse_options, execution_options.signedness);
+    }
+    let source = Source::read(&arguments.program_paths);
+    let (code, input) = match arguments.input {
+        Some(Input::Embedded) => {
+            let (code, input) = brainfuck::split_embedded_input(&source.code);
+            (code, Some(Input::Text(input.to_string())))
+        }
+        input => (source.code.as_str(), input),
+    };
+    let program = parse(code, &source, &parse_options);
+
+    if arguments.dump_ir {
+        eprintln!(
+            "optimized_instructions ({:?} -> {:?})",
+            program.op_code_count(),
+            program.instructions().len()
+        );
+        print!("{}", program.disassemble());
+        return;
+    }
+
+    match arguments.emit {
+        Some(Emit::C) => print!("{}", program.to_c_with_capacity(memory_capacity)),
+        Some(Emit::Rust) => print!("{}", program.to_rust_with_capacity(memory_capacity)),
+        Some(Emit::Llvm) => print!("{}", program.to_llvm_ir_with_capacity(memory_capacity)),
+        Some(Emit::Dot) => print!("{}", program.to_dot()),
+        None if arguments.bench => bench(&program, memory_capacity, &execution_options),
+        None => {
+            // `,` sees the end of input once these bytes are consumed
+            let input: Box<dyn Read> = match input {
+                Some(Input::Text(text)) => Box::new(io::Cursor::new(text.into_bytes())),
+                Some(Input::File(path)) => match fs::File::open(&path) {
+                    Ok(file) => Box::new(file),
+                    Err(error) => fail(format!("{}: {}", path, error)),
+                },
+                Some(Input::Embedded) => unreachable!(),
+                None => Box::new(io::stdin()),
+            };
+            let input: Box<dyn Read> = if arguments.normalize_newlines {
+                Box::new(brainfuck::NormalizeLineEndings::new(input))
+            } else {
+                input
+            };
+            let stdout = io::stdout();
+            let mut output = LastByte::new(stdout.lock());
+            if arguments.profile {
+                profile(&program, &source, memory_capacity, &execution_options, input, &mut output)
+            } else if arguments.warnings {
+                run_with_warnings(&program, &source, memory_capacity, &execution_options, input, &mut output)
+            } else if arguments.catch_interrupt {
+                run_interruptible(&program, &source, memory_capacity, &execution_options, input, &mut output)
+            } else {
+                run(
+                    &program,
+                    memory_capacity,
+                    &execution_options,
+                    arguments.breakpoints,
+                    match (arguments.trace, arguments.color) {
+                        (false, _) => Trace::Off,
+                        (true, ColorChoice::Always) => Trace::Colored,
+                        (true, ColorChoice::Never) => Trace::Plain,
+                        // An empty NO_COLOR does not count, see https://no-color.org
+                        (true, ColorChoice::Auto) if io::stderr().is_terminal() && env::var_os("NO_COLOR").map_or(true, |value| value.is_empty()) => Trace::Colored,
+                        (true, ColorChoice::Auto) => Trace::Plain,
+                    },
+                    input,
+                    &mut output,
+                )
+            }
+
+            if arguments.final_newline && output.last_byte.is_some_and(|byte| byte != b'\n') {
+                if let Err(error) = writeln!(output) {
+                    fail(error);
+                }
+            }
+        }
+    }
+}
+
+fn parse_arguments(args: &[String]) -> Option<Arguments> {
+    let mut positional = Vec::new();
+    let mut emit = None;
+    let mut breakpoints = false;
+    let mut dump_tape = false;
+    let mut trace = false;
+    let mut color = ColorChoice::Auto;
+    let mut saturating = false;
+    let mut input = None;
+    let mut dump_ir = false;
+    let mut profile = false;
+    let mut timeout = None;
+    let mut normalize_newlines = false;
+    let mut decimal_output = false;
+    let mut seven_bit_output = false;
+    let mut signed = false;
+    let mut bench = false;
+    let mut address_space = None;
+    let mut max_output = None;
+    let mut warnings = false;
+    let mut final_newline = false;
+    let mut catch_interrupt = false;
+    let mut repl = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--emit" => {
+                emit = match args.next()?.as_str() {
+                    "c" => Some(Emit::C),
+                    "rust" => Some(Emit::Rust),
+                    "llvm" => Some(Emit::Llvm),
+                    "dot" => Some(Emit::Dot),
+                    _ => return None,
+                }
+            }
+            "--breakpoints" => breakpoints = true,
+            "--dump-tape" => dump_tape = true,
+            "--trace" => trace = true,
+            "--color" => {
+                color = match args.next()?.as_str() {
+                    "auto" => ColorChoice::Auto,
+                    "always" => ColorChoice::Always,
+                    "never" => ColorChoice::Never,
+                    _ => return None,
+                }
+            }
+            "--saturating" => saturating = true,
+            "--dump-ir" => dump_ir = true,
+            "--profile" => profile = true,
+            "--timeout" => {
+                let seconds = args.next()?.parse::<f64>().ok()?;
+                timeout = Some(Duration::try_from_secs_f64(seconds).ok()?);
+            }
+            "--address-space" => {
+                let size = args.next()?;
+                address_space = Some(match parse_memory_size(size) {
+                    // Memory sizes are never zero
+                    Ok(address_space) => NonZeroUsize::new(address_space).unwrap(),
+                    Err(message) => fail(format!("invalid address space {:?}: {}", size, message)),
+                });
+            }
+            "--max-output" => {
+                let size = args.next()?;
+                max_output = Some(match parse_size(size) {
+                    Ok(max_output) => max_output as u64,
+                    Err(message) => fail(format!("invalid output limit {:?}: {}", size, message)),
+                });
+            }
+            "--repl" => repl = true,
+            "--input" => input = Some(Input::Text(args.next()?.to_string())),
+            "--input-file" => input = Some(Input::File(args.next()?.to_string())),
+            "--embedded-input" => input = Some(Input::Embedded),
+            "--normalize-newlines" => normalize_newlines = true,
+            "--decimal-output" => decimal_output = true,
+            "--7-bit-output" => seven_bit_output = true,
+            "--signed" => signed = true,
+            "--bench" => bench = true,
+            "--warnings" => warnings = true,
+            "--final-newline" => final_newline = true,
+            "--catch-interrupt" => catch_interrupt = true,
+            // `-` alone is stdin, anything else starting with it is an unknown option rather than a file
+            _ if arg.starts_with('-') && arg != "-" => return None,
+            _ => positional.push(arg),
+        }
+    }
+
+    // A last argument that is a whole number of bytes, like `30000` or `64K`, is the memory size unless a file of that name exists.
+    // Everything else is a program file.
+    let (program_paths, memory_capacity) = match positional.split_last() {
+        Some((last, rest)) if parse_size(last).is_ok() && !Path::new(last).exists() => (rest, Some(last)),
+        _ => (positional.as_slice(), None),
+    };
+    if program_paths.is_empty() != repl {
+        return None;
+    }
+
+    // The code generators only support wrapping arithmetic and unmasked byte output
+    if (saturating || decimal_output || seven_bit_output) && emit.is_some() {
+        return None;
+    }
+    // Profiling and benchmarking run the program without the hooks of these
+    if (profile || bench || warnings || catch_interrupt) && (trace || breakpoints) {
+        return None;
+    }
+    if [profile, bench, warnings, catch_interrupt].iter().filter(|&&enabled| enabled).count() > 1 {
+        return None;
+    }
+
+    Some(Arguments {
+        program_paths: program_paths.iter().map(|path| path.to_string()).collect(),
+        memory_capacity: memory_capacity.map(|memory_capacity| match parse_memory_size(memory_capacity) {
+            Ok(memory_capacity) => memory_capacity,
+            Err(message) => fail(format!("invalid memory size {:?}: {}", memory_capacity, message)),
+        }),
+        emit,
+        breakpoints,
+        dump_tape,
+        trace,
+        color,
+        saturating,
+        input,
+        dump_ir,
+        profile,
+        timeout,
+        normalize_newlines,
+        decimal_output,
+        seven_bit_output,
+        signed,
+        bench,
+        address_space,
+        max_output,
+        warnings,
+        final_newline,
+        catch_interrupt,
+    })
+}
+
+fn memory_capacity_from_env() -> Option<usize> {
+    match memory_capacity_from_variable(env::var(MEMORY_SIZE_VARIABLE)) {
+        Ok(memory_capacity) => memory_capacity,
+        Err(message) => fail(message),
+    }
+}
+
+/// The memory size in `value`, the result of reading `MEMORY_SIZE_VARIABLE`, or `None` if it is not set
+fn memory_capacity_from_variable(value: Result<String, env::VarError>) -> Result<Option<usize>, String> {
+    match value {
+        Ok(text) => match parse_memory_size(&text) {
+            Ok(memory_capacity) => Ok(Some(memory_capacity)),
+            Err(message) => Err(format!("invalid memory size {:?} in {}: {}", text, MEMORY_SIZE_VARIABLE, message)),
+        },
+        Err(env::VarError::NotPresent) => Ok(None),
+        Err(env::VarError::NotUnicode(text)) => {
+            Err(format!("invalid memory size {:?} in {}: not valid unicode", text, MEMORY_SIZE_VARIABLE))
+        }
+    }
+}
+
+/// Same as `parse_size`, but a tape needs at least one cell
+fn parse_memory_size(text: &str) -> Result<usize, &'static str> {
+    match parse_size(text)? {
+        0 => Err("the tape needs at least one cell"),
+        size => Ok(size),
+    }
+}
+
+/// Parses a number of bytes with an optional binary suffix, like `64K`, `2M` or `1G`
+fn parse_size(text: &str) -> Result<usize, &'static str> {
+    let (digits, factor) = match text.char_indices().last() {
+        Some((index, 'k' | 'K')) => (&text[..index], 1 << 10),
+        Some((index, 'm' | 'M')) => (&text[..index], 1 << 20),
+        Some((index, 'g' | 'G')) => (&text[..index], 1 << 30),
+        _ => (text, 1),
+    };
+
+    digits
+        .parse::<usize>()
+        .map_err(|_| "expected a number of bytes, optionally followed by K, M or G")?
+        .checked_mul(factor)
+        .ok_or("too large")
+}
+
+/// The program, concatenated from all program files
+struct Source {
+    code: String,
+    /// Path and offset in `code` of every file
+    files: Vec<(String, usize)>,
+}
+
+impl Source {
+    fn read(paths: &[String]) -> Source {
+        let mut code = String::new();
+        let mut files = Vec::new();
+        for path in paths {
+            files.push((path.to_string(), code.len()));
+            code.push_str(&read_file(path));
+        }
+        Source { code, files }
+    }
+
+    /// Finds the file containing `byte_offset` and the location in it
+    fn locate(&self, byte_offset: usize) -> String {
+        // Empty files start where the next one does, so take the last file starting at or before the offset
+        let index = self.files.partition_point(|&(_, start)| start <= byte_offset) - 1;
+        let (path, start) = &self.files[index];
+        let end = self.files.get(index + 1).map_or(self.code.len(), |&(_, end)| end);
+        let span = brainfuck::SourceSpan::locate(&self.code[*start..end], byte_offset - start);
+        format!("{}: {}", path, span)
+    }
+}
+
+/// Passes everything through to `inner` and remembers the last byte written, for `--final-newline`
+struct LastByte<W> {
+    inner: W,
+    last_byte: Option<u8>,
+}
+
+impl<W: Write> LastByte<W> {
+    fn new(inner: W) -> LastByte<W> {
+        LastByte { inner, last_byte: None }
+    }
+}
+
+impl<W: Write> Write for LastByte<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        if written > 0 {
+            self.last_byte = Some(buf[written - 1]);
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
     }
 }
 
+/// Reads the program source, `-` reads it from stdin
 fn read_file(file_name: &str) -> String {
-    fs::read_to_string(file_name).unwrap()
+    let source = if file_name == "-" {
+        let mut source = String::new();
+        io::stdin().read_to_string(&mut source).map(|_| source)
+    } else {
+        fs::read_to_string(file_name)
+    };
+
+    match source {
+        Ok(source) => source,
+        Err(error) => fail(format!("{}: {}", file_name, error)),
+    }
+}
+
+/// Parses `code`, which is `source` or the start of it
+fn parse(code: &str, source: &Source, options: &brainfuck::ParseOptions) -> brainfuck::Program {
+    match brainfuck::Program::parse_with_options(code, options) {
+        Ok(program) => program,
+        Err(brainfuck::ParseError::UnexpectedLoopEnd { span }) => {
+            fail(format!("unbalanced brackets: `]` without a matching `[` at {}", source.locate(span.byte_offset)))
+        }
+        Err(brainfuck::ParseError::UnclosedLoopHead { span }) => {
+            fail(format!("unbalanced brackets: `[` without a matching `]` at {}", source.locate(span.byte_offset)))
+        }
+        Err(brainfuck::ParseError::LoopNestingTooDeep { depth, span }) => fail(format!(
+            "loops nested too deeply: the `[` at {} opens a loop at depth {}",
+            source.locate(span.byte_offset),
+            depth
+        )),
+    }
+}
+
+fn run(
+    program: &brainfuck::Program,
+    memory_capacity: usize,
+    options: &brainfuck::ExecutionOptions,
+    breakpoints: bool,
+    trace: Trace,
+    input: Box<dyn Read>,
+    output: &mut dyn Write,
+) {
+    let mut memory = vec![0u8; memory_capacity];
+
+    let result = if let Trace::Plain | Trace::Colored = trace {
+        let stderr = io::stderr();
+        let mut stderr = stderr.lock();
+        program.run_io_with_trace(
+            &mut memory,
+            input,
+            output,
+            options,
+            |instruction_pointer, instruction, data_pointer, memory| {
+                let cell = options.signedness.render(memory[data_pointer]);
+                let _ = match trace {
+                    Trace::Colored => writeln!(
+                        stderr,
+                        "{:>6}: {}{}\x1b[0m, data pointer {}, cell \x1b[1m{}\x1b[0m",
+                        instruction_pointer,
+                        trace_color(instruction),
+                        instruction,
+                        data_pointer,
+                        cell
+                    ),
+                    _ => writeln!(stderr, "{:>6}: {}, data pointer {}, cell {}", instruction_pointer, instruction, data_pointer, cell),
+                };
+                if breakpoints && *instruction == brainfuck::Instruction::Breakpoint {
+                    let _ = write!(
+                        stderr,
+                        "{}",
+                        brainfuck::dump_tape_with_signedness(memory, data_pointer, 8, options.signedness)
+                    );
+                }
+            },
+        )
+    } else if breakpoints {
+        program.run_io_with_breakpoints(
+            &mut memory,
+            input,
+            output,
+            options,
+            |data_pointer, memory| {
+                eprint!(
+                    "breakpoint: data pointer {}\n{}",
+                    data_pointer,
+                    brainfuck::dump_tape_with_signedness(memory, data_pointer, 8, options.signedness)
+                );
+            },
+        )
+    } else {
+        program.run_io_with_options(&mut memory, input, output, options)
+    };
+
+    match result {
+        Ok(halt) => check_halt(halt),
+        Err(error) => fail(error),
+    }
+}
+
+/// The ANSI escape sequence `--trace` starts an instruction with: pointer moves are cyan, value changes yellow and loops magenta
+fn trace_color(instruction: &brainfuck::Instruction) -> &'static str {
+    match instruction {
+        brainfuck::Instruction::IncrementPointer(_)
+        | brainfuck::Instruction::DecrementPointer(_)
+        | brainfuck::Instruction::ScanRight(_)
+        | brainfuck::Instruction::ScanLeft(_) => "\x1b[36m",
+        brainfuck::Instruction::IncrementValue(_)
+        | brainfuck::Instruction::DecrementValue(_)
+        | brainfuck::Instruction::SetZero
+        | brainfuck::Instruction::SetValue(_)
+        | brainfuck::Instruction::FillZero { .. }
+        | brainfuck::Instruction::MultiplyAdd(_)
+        | brainfuck::Instruction::CopyTo { .. }
+        | brainfuck::Instruction::AddAtOffset { .. } => "\x1b[33m",
+        brainfuck::Instruction::LoopHead(_) | brainfuck::Instruction::LoopEnd(_) => "\x1b[35m",
+        _ => "",
+    }
+}
+
+fn check_halt(halt: brainfuck::Halt) {
+    match halt {
+        brainfuck::Halt::Timeout => fail("timeout: the program did not finish in time"),
+        brainfuck::Halt::OutputLimitReached => fail("output limit: the program tried to write more than --max-output allows"),
+        _ => {}
+    }
+}
+
+/// Reports an error the user can fix, like a missing file or an invalid program, and exits with code 1
+fn fail<M: fmt::Display>(message: M) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+/// Runs the program and prints the loops with the most iterations to stderr
+fn profile(
+    program: &brainfuck::Program,
+    source: &Source,
+    memory_capacity: usize,
+    options: &brainfuck::ExecutionOptions,
+    input: Box<dyn Read>,
+    output: &mut dyn Write,
+) {
+    let mut memory = vec![0u8; memory_capacity];
+
+    let (halt, stats) = match program.run_io_with_stats(&mut memory, input, output, options) {
+        Ok(result) => result,
+        Err(error) => fail(error),
+    };
+
+    let mut loops: Vec<_> = stats.loops.into_iter().collect();
+    loops.sort_by_key(|&(_, loop_stats)| Reverse(loop_stats.iterations));
+
+    eprintln!("{} steps, {} loop iterations", stats.steps, stats.loop_iterations);
+    for (loop_head_address, loop_stats) in loops.iter().take(10) {
+        eprintln!(
+            "loop at {} ran {} iterations in {} entries",
+            source.locate(program.source_map()[*loop_head_address].start),
+            loop_stats.iterations,
+            loop_stats.entries
+        );
+    }
+    // The profile of a program that did not finish is useful as well
+    check_halt(halt);
+}
+
+/// Runs the program without I/O and prints how long it took to stderr
+fn bench(program: &brainfuck::Program, memory_capacity: usize, options: &brainfuck::ExecutionOptions) {
+    let mut memory = vec![0u8; memory_capacity];
+    let report = match program.bench_run(&mut memory, options) {
+        Ok(report) => report,
+        Err(error) => fail(error),
+    };
+
+    eprintln!(
+        "{} steps in {:.3}s, {:.1}M steps per second",
+        report.steps,
+        report.elapsed.as_secs_f64(),
+        report.instructions_per_second() / 1e6
+    );
+    check_halt(report.halt);
+}
+
+/// Prints the lints of the program to stderr, then runs it and prints what `Program::run_io_with_lints` finds as well
+fn run_with_warnings(
+    program: &brainfuck::Program,
+    source: &Source,
+    memory_capacity: usize,
+    options: &brainfuck::ExecutionOptions,
+    input: Box<dyn Read>,
+    output: &mut dyn Write,
+) {
+    for lint in program.lint() {
+        eprintln!("warning: {}: {}", source.locate(lint.source.start), lint);
+    }
+
+    let mut memory = vec![0u8; memory_capacity];
+    let result = program.run_io_with_lints(&mut memory, input, output, options, |lint| {
+        let address = match lint {
+            brainfuck::RuntimeLint::UninitializedRead { address, .. } | brainfuck::RuntimeLint::PointerBelowStart { address } => address,
+        };
+        eprintln!("warning: {}: {}", source.locate(program.source_map()[address].start), lint);
+    });
+
+    match result {
+        Ok(halt) => check_halt(halt),
+        Err(error) => fail(error),
+    }
+}
+
+/// Set by the SIGINT handler installed for `--catch-interrupt`
+#[cfg(feature = "signals")]
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(feature = "signals")]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    // The interpreter only notices the flag between instructions, a second Ctrl-C while it waits for input exits right away
+    unsafe {
+        libc::signal(libc::SIGINT, libc::SIG_DFL);
+    }
+}
+
+/// Runs the program until it finishes or Ctrl-C is pressed, then prints where it stopped and the cells around the data pointer to stderr
+#[cfg(feature = "signals")]
+fn run_interruptible(
+    program: &brainfuck::Program,
+    source: &Source,
+    memory_capacity: usize,
+    options: &brainfuck::ExecutionOptions,
+    input: Box<dyn Read>,
+    output: &mut dyn Write,
+) {
+    let options = brainfuck::ExecutionOptions {
+        interrupt: Some(brainfuck::Interrupt::new(&INTERRUPTED)),
+        ..*options
+    };
+    unsafe {
+        libc::signal(libc::SIGINT, on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t);
+    }
+
+    let mut memory = vec![0u8; memory_capacity];
+    // The last instruction executed and the data pointer after it
+    let mut last_step = None;
+    let result = program.run_io_with_trace(&mut memory, input, output, &options, |instruction_pointer, _, data_pointer, _| {
+        last_step = Some((instruction_pointer, data_pointer));
+    });
+
+    match result {
+        Ok(brainfuck::Halt::Interrupted) => {
+            let data_pointer = match last_step {
+                Some((instruction_pointer, data_pointer)) => {
+                    eprintln!(
+                        "interrupted after instruction {} ({}) at {}, data pointer {}",
+                        instruction_pointer,
+                        program.instructions()[instruction_pointer],
+                        source.locate(program.source_map()[instruction_pointer].start),
+                        data_pointer
+                    );
+                    data_pointer
+                }
+                None => {
+                    eprintln!("interrupted before the first instruction");
+                    0
+                }
+            };
+            eprint!("{}", brainfuck::dump_tape_with_signedness(&memory, data_pointer, 8, options.signedness));
+            process::exit(130);
+        }
+        Ok(halt) => check_halt(halt),
+        Err(error) => fail(error),
+    }
+}
+
+#[cfg(not(feature = "signals"))]
+fn run_interruptible(
+    _program: &brainfuck::Program,
+    _source: &Source,
+    _memory_capacity: usize,
+    _options: &brainfuck::ExecutionOptions,
+    _input: Box<dyn Read>,
+    _output: &mut dyn Write,
+) {
+    fail("--catch-interrupt needs the signals feature, build with --features signals");
 }
 
-fn run(program: &str, memory_capacity: Option<usize>) {
-    if let Some(program) = brainfuck::Program::parse(program) {
-        let memory_capacity = memory_capacity.unwrap_or(1048576);
-        let mut memory = vec![0u8; memory_capacity];
-        program.run(&mut memory);
+/// Runs every line read from stdin on the same tape, continuing where the previous line left the data pointer
+fn repl(memory_capacity: usize, parse_options: &brainfuck::ParseOptions, signedness: brainfuck::Signedness) {
+    let parse_options = brainfuck::ParseOptions {
+        fragment: true,
+        ..*parse_options
+    };
+    let mut session = brainfuck::Session::<u8>::new(memory_capacity);
+
+    let stdin = io::stdin();
+    let stdout = io::stdout();
+    loop {
+        eprint!("> ");
+        let mut line = String::new();
+        match stdin.lock().read_line(&mut line) {
+            Ok(0) => break,
+            Ok(_) => {}
+            Err(error) => fail(error),
+        }
+
+        let program = match brainfuck::Program::parse_with_options(&line, &parse_options) {
+            Ok(program) => program,
+            Err(error) => {
+                eprintln!("{}", error);
+                continue;
+            }
+        };
+
+        // Lines cannot read from stdin, it is what the lines come from
+        if let Err(error) = session.run_program_io(&program, io::empty(), stdout.lock()) {
+            eprintln!("{}", error);
+        }
+        eprint!(
+            "data pointer {}\n{}",
+            session.data_pointer(),
+            brainfuck::dump_tape_with_signedness(session.memory(), session.data_pointer(), 8, signedness)
+        );
     }
 }
 
-fn help(program_line: &str) {
-    panic!("Usage:\n\t{} <program.bf> [memory-size]\n\nMemory size in bytes. Defaults to 1MiB (1048576 bytes)", program_line)
+/// Prints the usage and exits with code 2, for invalid arguments
+fn help(program_line: &str) -> ! {
+    eprintln!("{}", usage(program_line));
+    process::exit(2);
+}
+
+fn usage(program_line: &str) -> String {
+    format!(
+        concat!(
+            "Usage:\n",
+            "\t{} [--help] [--emit c|rust|llvm|dot] [--dump-ir] [--profile] [--timeout <seconds>] ",
+                "[--address-space <size>] [--max-output <size>] [--breakpoints] [--dump-tape] [--trace] ",
+                "[--color auto|always|never] [--saturating] [--input <text> | --input-file <path> | --embedded-input] ",
+                "[--normalize-newlines] [--decimal-output] [--7-bit-output] [--signed] [--bench] [--warnings] ",
+                "[--final-newline] [--catch-interrupt] <program.bf>... [memory-size]\n",
+            "\t{} --repl [--signed] [memory-size]\n",
+            "\n",
+            "Several program files are concatenated in the given order. Use - as the program to read it from stdin. ",
+                "Combine it with --input, --input-file or --embedded-input to pass input to the program\n",
+            "Memory size in bytes, a K, M or G suffix multiplies it by 1024, 1024² or 1024³. Defaults to the ",
+                "BF_MEMORY_SIZE environment variable if it is set and to 1M (1048576 bytes) otherwise. A last argument ",
+                "naming an existing file is a program instead\n",
+            "--emit c|rust|llvm|dot prints the program translated to C, Rust or LLVM IR, or its control flow graph in ",
+                "Graphviz DOT, instead of running it\n",
+            "--dump-ir prints the optimized instructions instead of running the program\n",
+            "--profile prints the 10 loops with the most iterations to stderr after running the program, it cannot be ",
+                "combined with --breakpoints or --trace\n",
+            "--timeout <seconds> stops the program with an error once it ran for this long, e.g. 0.5 or 5\n",
+            "--address-space <size> makes the data pointer wrap around at this many cells like in interpreters with a ",
+                "smaller pointer, e.g. 4G for a 32-bit one. It accepts the same suffixes as the memory size\n",
+            "--max-output <size> stops the program with an error instead of writing more than this many bytes. It ",
+                "accepts the same suffixes as the memory size\n",
+            "--breakpoints treats # as a breakpoint that prints the data pointer and the surrounding cells to stderr\n",
+            "--dump-tape treats ? as a command that prints the data pointer and the surrounding cells to stderr, ",
+                "separate from the output of the program\n",
+            "--trace prints every executed instruction with the data pointer and the current cell afterwards to ",
+                "stderr\n",
+            "--color auto|always|never colors the instructions in --trace by what they do. auto colors if stderr is a ",
+                "terminal and NO_COLOR is not set\n",
+            "--saturating makes + and - stop at 255 and 0 instead of wrapping around, it cannot be combined with ",
+                "--emit\n",
+            "--input <text>, --input-file <path> make , read from the given text or file instead of stdin. Once it is ",
+                "consumed, , sees the end of input\n",
+            "--embedded-input splits the program at the first !, everything after it is the input for ,\n",
+            "--normalize-newlines makes , read \\r\\n line endings as \\n\n",
+            "--decimal-output makes . print the cell as a decimal number followed by a space instead of as a byte, it ",
+                "cannot be combined with --emit\n",
+            "--7-bit-output clears the highest bit of every byte . writes, for channels that only accept ASCII. This ",
+                "changes what programs print, it cannot be combined with --emit\n",
+            "--signed shows cells as signed numbers in -128..127 for --decimal-output, --trace, --breakpoints and ",
+                "--repl\n",
+            "--bench runs the program with , reading zeros and . writing nothing and prints the time it took to ",
+                "stderr, it cannot be combined with --profile, --breakpoints or --trace\n",
+            "--warnings prints loops that never end and reads of cells the program never wrote to stderr, it cannot ",
+                "be combined with --profile, --bench, --breakpoints or --trace\n",
+            "--final-newline writes a newline after the output of the program if it does not end with one already\n",
+            "--catch-interrupt stops the program on Ctrl-C and prints where it was and the cells around the data ",
+                "pointer to stderr. It needs the signals feature and cannot be combined with --profile, --bench, ",
+                "--warnings, --breakpoints or --trace\n",
+            "--repl runs every line typed on the same tape and prints the cells around the data pointer afterwards\n",
+            "--help, -h prints this help to stdout",
+        ),
+        program_line, program_line
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arguments(args: &[&str]) -> Option<Arguments> {
+        parse_arguments(&args.iter().map(|arg| arg.to_string()).collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn unknown_options_are_rejected() {
+        assert!(arguments(&["--trace", "program.bf"]).is_some());
+        assert!(arguments(&["-"]).is_some());
+        assert!(arguments(&["--tarce", "program.bf"]).is_none());
+        assert!(arguments(&["program.bf", "-t"]).is_none());
+        assert!(arguments(&["--", "program.bf"]).is_none());
+    }
+
+    #[test]
+    fn sizes_may_have_a_binary_suffix() {
+        assert_eq!(parse_size("300"), Ok(300));
+        assert_eq!(parse_size("64k"), Ok(64 << 10));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("2M"), Ok(2 << 20));
+        assert_eq!(parse_size("1g"), Ok(1 << 30));
+        assert_eq!(parse_size("0"), Ok(0));
+        assert_eq!(parse_size("0K"), Ok(0));
+    }
+
+    #[test]
+    fn invalid_sizes_are_rejected() {
+        for text in ["", "K", "-1", "1.5M", "12 K", "1KB", "ten", "1T"] {
+            assert!(parse_size(text).is_err(), "{:?}", text);
+        }
+        assert_eq!(parse_size(&format!("{}0", usize::MAX)), Err("expected a number of bytes, optionally followed by K, M or G"));
+        assert_eq!(parse_size(&format!("{}G", usize::MAX >> 20)), Err("too large"));
+        assert_eq!(parse_size(&format!("{}G", usize::MAX >> 30)), Ok((usize::MAX >> 30) << 30));
+    }
+
+    #[test]
+    fn memory_sizes_need_a_cell() {
+        assert_eq!(parse_memory_size("1"), Ok(1));
+        assert_eq!(parse_memory_size("0"), Err("the tape needs at least one cell"));
+        assert_eq!(parse_memory_size("0M"), Err("the tape needs at least one cell"));
+        assert!(parse_memory_size("x").is_err());
+    }
+
+    #[test]
+    fn memory_size_variable_is_optional() {
+        assert_eq!(memory_capacity_from_variable(Ok("64K".to_string())), Ok(Some(64 << 10)));
+        assert_eq!(memory_capacity_from_variable(Err(env::VarError::NotPresent)), Ok(None));
+        assert_eq!(
+            memory_capacity_from_variable(Ok("0".to_string())),
+            Err("invalid memory size \"0\" in BF_MEMORY_SIZE: the tape needs at least one cell".to_string())
+        );
+        assert!(memory_capacity_from_variable(Ok("lots".to_string())).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn memory_size_variable_must_be_unicode() {
+        use std::ffi::OsString;
+        use std::os::unix::ffi::OsStringExt;
+
+        let text = OsString::from_vec(vec![b'1', 0xff]);
+        assert!(memory_capacity_from_variable(Err(env::VarError::NotUnicode(text))).is_err());
+    }
 }
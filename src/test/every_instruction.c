@@ -0,0 +1,33 @@
+#include <stdio.h>
+#include <string.h>
+
+static unsigned char mem[16];
+
+int main(void) {
+    unsigned char *ptr = mem;
+
+    ptr += 2;
+    ptr -= 1;
+    *ptr += 3;
+    *ptr -= 1;
+    putchar(*ptr);
+    for (int i = 0; i < 2; i++) putchar(*ptr);
+    { int c; fflush(stdout); c = getchar(); if (c != EOF) *ptr = c; }
+    for (int i = 0; i < 2; i++) { int c; fflush(stdout); c = getchar(); if (c != EOF) *ptr = c; }
+    while (*ptr) {
+        *ptr = 0;
+        *ptr = -2;
+        memset(ptr, 0, 3); ptr += 2;
+        if (*ptr) { ptr[1] += *ptr * 2; ptr[-1] += *ptr * -3; *ptr = 0; }
+        if (*ptr) { ptr[-2] += *ptr; ptr[1] += *ptr; *ptr = 0; }
+        while (*ptr) ptr += 2;
+        while (*ptr) ptr -= 1;
+        ptr[-1] += 5;
+        /* breakpoint */
+        /* dump tape */
+        /* @end */
+    }
+
+    fflush(stdout);
+    return 0;
+}
@@ -0,0 +1,208 @@
+@mem = internal global [16 x i8] zeroinitializer
+
+declare i32 @putchar(i32)
+declare i32 @getchar()
+declare i32 @fflush(ptr)
+declare void @llvm.memset.p0.i64(ptr, i8, i64, i1)
+
+define i32 @main() {
+entry:
+  %ptr = alloca i64
+  store i64 0, ptr %ptr
+  %v0 = load i64, ptr %ptr
+  %v1 = add i64 %v0, 2
+  store i64 %v1, ptr %ptr
+  %v2 = load i64, ptr %ptr
+  %v3 = add i64 %v2, -1
+  store i64 %v3, ptr %ptr
+  %v4 = load i64, ptr %ptr
+  %v5 = add i64 %v4, 0
+  %v6 = getelementptr inbounds i8, ptr @mem, i64 %v5
+  %v7 = load i8, ptr %v6
+  %v8 = add i8 %v7, 3
+  store i8 %v8, ptr %v6
+  %v9 = load i64, ptr %ptr
+  %v10 = add i64 %v9, 0
+  %v11 = getelementptr inbounds i8, ptr @mem, i64 %v10
+  %v12 = load i8, ptr %v11
+  %v13 = add i8 %v12, -1
+  store i8 %v13, ptr %v11
+  %v14 = load i64, ptr %ptr
+  %v15 = add i64 %v14, 0
+  %v16 = getelementptr inbounds i8, ptr @mem, i64 %v15
+  %v17 = load i8, ptr %v16
+  %v18 = zext i8 %v17 to i32
+  %v19 = call i32 @putchar(i32 %v18)
+  %v20 = load i64, ptr %ptr
+  %v21 = add i64 %v20, 0
+  %v22 = getelementptr inbounds i8, ptr @mem, i64 %v21
+  %v23 = load i8, ptr %v22
+  br label %repeat24.start
+repeat24.start:
+  br label %repeat24
+repeat24:
+  %v25 = phi i64 [ 0, %repeat24.start ], [ %v26, %repeat24.next ]
+  %v27 = zext i8 %v23 to i32
+  %v28 = call i32 @putchar(i32 %v27)
+  br label %repeat24.next
+repeat24.next:
+  %v26 = add i64 %v25, 1
+  %v29 = icmp ult i64 %v26, 2
+  br i1 %v29, label %repeat24, label %repeat24.end
+repeat24.end:
+  %v30 = call i32 @fflush(ptr null)
+  %v31 = call i32 @getchar()
+  %v32 = icmp eq i32 %v31, -1
+  br i1 %v32, label %read31.end, label %read31
+read31:
+  %v33 = trunc i32 %v31 to i8
+  %v34 = load i64, ptr %ptr
+  %v35 = add i64 %v34, 0
+  %v36 = getelementptr inbounds i8, ptr @mem, i64 %v35
+  store i8 %v33, ptr %v36
+  br label %read31.end
+read31.end:
+  br label %repeat37.start
+repeat37.start:
+  br label %repeat37
+repeat37:
+  %v38 = phi i64 [ 0, %repeat37.start ], [ %v39, %repeat37.next ]
+  %v40 = call i32 @fflush(ptr null)
+  %v41 = call i32 @getchar()
+  %v42 = icmp eq i32 %v41, -1
+  br i1 %v42, label %read41.end, label %read41
+read41:
+  %v43 = trunc i32 %v41 to i8
+  %v44 = load i64, ptr %ptr
+  %v45 = add i64 %v44, 0
+  %v46 = getelementptr inbounds i8, ptr @mem, i64 %v45
+  store i8 %v43, ptr %v46
+  br label %read41.end
+read41.end:
+  br label %repeat37.next
+repeat37.next:
+  %v39 = add i64 %v38, 1
+  %v47 = icmp ult i64 %v39, 2
+  br i1 %v47, label %repeat37, label %repeat37.end
+repeat37.end:
+  %v48 = load i64, ptr %ptr
+  %v49 = add i64 %v48, 0
+  %v50 = getelementptr inbounds i8, ptr @mem, i64 %v49
+  %v51 = load i8, ptr %v50
+  %v52 = icmp ne i8 %v51, 0
+  br i1 %v52, label %loop0, label %loop0.end
+loop0:
+  %v53 = load i64, ptr %ptr
+  %v54 = add i64 %v53, 0
+  %v55 = getelementptr inbounds i8, ptr @mem, i64 %v54
+  store i8 0, ptr %v55
+  %v56 = load i64, ptr %ptr
+  %v57 = add i64 %v56, 0
+  %v58 = getelementptr inbounds i8, ptr @mem, i64 %v57
+  store i8 -2, ptr %v58
+  %v59 = load i64, ptr %ptr
+  %v60 = add i64 %v59, 0
+  %v61 = getelementptr inbounds i8, ptr @mem, i64 %v60
+  call void @llvm.memset.p0.i64(ptr %v61, i8 0, i64 3, i1 false)
+  %v62 = load i64, ptr %ptr
+  %v63 = add i64 %v62, 2
+  store i64 %v63, ptr %ptr
+  %v65 = load i64, ptr %ptr
+  %v66 = add i64 %v65, 0
+  %v67 = getelementptr inbounds i8, ptr @mem, i64 %v66
+  %v68 = load i8, ptr %v67
+  %v69 = icmp ne i8 %v68, 0
+  br i1 %v69, label %multiply64, label %multiply64.end
+multiply64:
+  %v70 = load i64, ptr %ptr
+  %v71 = add i64 %v70, 1
+  %v72 = getelementptr inbounds i8, ptr @mem, i64 %v71
+  %v73 = load i8, ptr %v72
+  %v74 = mul i8 %v68, 2
+  %v75 = add i8 %v73, %v74
+  store i8 %v75, ptr %v72
+  %v76 = load i64, ptr %ptr
+  %v77 = add i64 %v76, -1
+  %v78 = getelementptr inbounds i8, ptr @mem, i64 %v77
+  %v79 = load i8, ptr %v78
+  %v80 = mul i8 %v68, -3
+  %v81 = add i8 %v79, %v80
+  store i8 %v81, ptr %v78
+  %v82 = load i64, ptr %ptr
+  %v83 = add i64 %v82, 0
+  %v84 = getelementptr inbounds i8, ptr @mem, i64 %v83
+  store i8 0, ptr %v84
+  br label %multiply64.end
+multiply64.end:
+  %v86 = load i64, ptr %ptr
+  %v87 = add i64 %v86, 0
+  %v88 = getelementptr inbounds i8, ptr @mem, i64 %v87
+  %v89 = load i8, ptr %v88
+  %v90 = icmp ne i8 %v89, 0
+  br i1 %v90, label %copy85, label %copy85.end
+copy85:
+  %v91 = load i64, ptr %ptr
+  %v92 = add i64 %v91, -2
+  %v93 = getelementptr inbounds i8, ptr @mem, i64 %v92
+  %v94 = load i8, ptr %v93
+  %v95 = add i8 %v94, %v89
+  store i8 %v95, ptr %v93
+  %v96 = load i64, ptr %ptr
+  %v97 = add i64 %v96, 1
+  %v98 = getelementptr inbounds i8, ptr @mem, i64 %v97
+  %v99 = load i8, ptr %v98
+  %v100 = add i8 %v99, %v89
+  store i8 %v100, ptr %v98
+  %v101 = load i64, ptr %ptr
+  %v102 = add i64 %v101, 0
+  %v103 = getelementptr inbounds i8, ptr @mem, i64 %v102
+  store i8 0, ptr %v103
+  br label %copy85.end
+copy85.end:
+  br label %scan104
+scan104:
+  %v105 = load i64, ptr %ptr
+  %v106 = add i64 %v105, 0
+  %v107 = getelementptr inbounds i8, ptr @mem, i64 %v106
+  %v108 = load i8, ptr %v107
+  %v109 = icmp ne i8 %v108, 0
+  br i1 %v109, label %scan104.step, label %scan104.end
+scan104.step:
+  %v110 = load i64, ptr %ptr
+  %v111 = add i64 %v110, 2
+  store i64 %v111, ptr %ptr
+  br label %scan104
+scan104.end:
+  br label %scan112
+scan112:
+  %v113 = load i64, ptr %ptr
+  %v114 = add i64 %v113, 0
+  %v115 = getelementptr inbounds i8, ptr @mem, i64 %v114
+  %v116 = load i8, ptr %v115
+  %v117 = icmp ne i8 %v116, 0
+  br i1 %v117, label %scan112.step, label %scan112.end
+scan112.step:
+  %v118 = load i64, ptr %ptr
+  %v119 = add i64 %v118, -1
+  store i64 %v119, ptr %ptr
+  br label %scan112
+scan112.end:
+  %v120 = load i64, ptr %ptr
+  %v121 = add i64 %v120, -1
+  %v122 = getelementptr inbounds i8, ptr @mem, i64 %v121
+  %v123 = load i8, ptr %v122
+  %v124 = add i8 %v123, 5
+  store i8 %v124, ptr %v122
+  ; breakpoint
+  ; dump tape
+  ; @end
+  %v125 = load i64, ptr %ptr
+  %v126 = add i64 %v125, 0
+  %v127 = getelementptr inbounds i8, ptr @mem, i64 %v126
+  %v128 = load i8, ptr %v127
+  %v129 = icmp ne i8 %v128, 0
+  br i1 %v129, label %loop0, label %loop0.end
+loop0.end:
+  %v130 = call i32 @fflush(ptr null)
+  ret i32 0
+}
@@ -0,0 +1,36 @@
+#![allow(unused)]
+
+use std::io::{self, Read, Write};
+
+fn main() {
+    let mut memory = vec![0u8; 16];
+    let mut ptr: usize = 0;
+    let stdin = io::stdin();
+    let mut input = io::BufReader::new(stdin.lock()).bytes();
+    let stdout = io::stdout();
+    let mut output = stdout.lock();
+
+    ptr += 2;
+    ptr -= 1;
+    memory[ptr] = memory[ptr].wrapping_add(3);
+    memory[ptr] = memory[ptr].wrapping_sub(1);
+    output.write_all(&[memory[ptr]]).unwrap();
+    for _ in 0..2 { output.write_all(&[memory[ptr]]).unwrap(); }
+    output.flush().unwrap(); if let Some(byte) = input.next() { memory[ptr] = byte.unwrap(); }
+    output.flush().unwrap(); for _ in 0..2 { if let Some(byte) = input.next() { memory[ptr] = byte.unwrap(); } }
+    while memory[ptr] != 0 {
+        memory[ptr] = 0;
+        memory[ptr] = 254;
+        memory[ptr..ptr + 3].fill(0); ptr += 2;
+        if memory[ptr] != 0 { memory[ptr + 1] = memory[ptr + 1].wrapping_add(memory[ptr].wrapping_mul(2)); memory[ptr - 1] = memory[ptr - 1].wrapping_add(memory[ptr].wrapping_mul(253)); memory[ptr] = 0; }
+        if memory[ptr] != 0 { memory[ptr - 2] = memory[ptr - 2].wrapping_add(memory[ptr]); memory[ptr + 1] = memory[ptr + 1].wrapping_add(memory[ptr]); memory[ptr] = 0; }
+        while memory[ptr] != 0 { ptr += 2; }
+        while memory[ptr] != 0 { ptr -= 1; }
+        memory[ptr - 1] = memory[ptr - 1].wrapping_add(5);
+        // breakpoint
+        // dump tape
+        // @end
+    }
+
+    output.flush().unwrap();
+}